@@ -1,11 +1,15 @@
-use super::parser::{Atom, Expr, PrintableList};
+use super::parser::{Atom, Condition, Expr, List, PrintableList, CHAR_NAMES};
 use crate::utils::*;
 use std::fmt::{Display, Formatter, Result};
 
 impl Display for Atom {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         let s = match self {
-            Atom::Str(s) => s.clone(),
+            Atom::Str(s) => format!("\"{}\"", escape(&s.borrow())),
+            Atom::Char(c) => match CHAR_NAMES.iter().find(|(_, named)| named == c) {
+                Some((name, _)) => format!("#\\{}", name),
+                None => format!("#\\{}", c),
+            },
             Atom::Num(n) => n.to_string(),
             Atom::Bool(b) => {
                 if *b {
@@ -14,55 +18,130 @@ impl Display for Atom {
                     "#f".to_string()
                 }
             }
-            Atom::Symbol(s) => s.clone(),
+            Atom::Symbol(s) | Atom::Uninterned(s) => s.clone(),
             Atom::Nil => "()".to_string(),
+            Atom::Eof => "#<eof>".to_string(),
             Atom::Native(_) => "<native>".to_string(),
+            Atom::Syntax(_) => "<syntax>".to_string(),
         };
         write!(f, "{}", s)
     }
 }
 
+// Writes `s' back with the escapes the reader understands
+fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                res.push('\\');
+                res.push(c);
+            }
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            c if c.is_control() => res.push_str(&format!("\\x{:x};", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
 impl Display for Expr {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        let s = match self {
-            Expr::Atom(a) => format!("{}", a),
-            Expr::List(l) => format!("{}", PrintableList(l.clone())),
-            Expr::Quote(q) => format!("(quote {})", q),
-            Expr::Quasiquote(q) => format!("`{}", q),
-            Expr::Unquote(u) => format!(",{}", u),
-            Expr::Lambda(_l) => format!("<lambda>"),
-        };
-        write!(f, "{}", s)
+        fmt_expr(self, true, f)
     }
 }
 
-impl Display for PrintableList {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        let list = &self.0;
-        let mut s = String::from("(");
-        let last = list.back().unwrap();
+/// An expression printed the way `display` does, with strings and
+/// characters written as their contents instead of as literals.
+pub struct Displayed<'a>(pub &'a Expr);
 
-        let mut i = 1;
-        for expr in list.iter() {
-            let lag = format!("{}", expr);
-            s.push_str(&lag);
+impl Display for Displayed<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        fmt_expr(self.0, false, f)
+    }
+}
 
-            i += 1;
-            if i == list.len() {
-                break;
+// Written as literals when `write' is set, as `write' does
+fn fmt_expr(expr: &Expr, write: bool, f: &mut Formatter<'_>) -> Result {
+    let s = match expr {
+        Expr::Atom(a) => match &**a {
+            Atom::Str(s) if !write => s.borrow().clone(),
+            Atom::Char(c) if !write => c.to_string(),
+            a => format!("{}", a),
+        },
+        Expr::List(l) => return fmt_list(l, write, f),
+        Expr::Quote(q) => return fmt_prefixed("(quote ", q, ")", write, f),
+        Expr::Quasiquote(q) => return fmt_prefixed("`", q, "", write, f),
+        Expr::Unquote(u) => return fmt_prefixed(",", u, "", write, f),
+        Expr::UnquoteSplicing(u) => return fmt_prefixed(",@", u, "", write, f),
+        Expr::Lambda(l) => match &l.name {
+            Some(name) => format!("<lambda {}>", name),
+            None => "<lambda>".to_string(),
+        },
+        Expr::CaseLambda(_) => "<case-lambda>".to_string(),
+        Expr::Macro(_) => "<macro>".to_string(),
+        Expr::Continuation(_) => "<continuation>".to_string(),
+        Expr::Condition(c) => format!("<error {}>", c),
+        Expr::Promise(_) => "<promise>".to_string(),
+        Expr::Parameter(_) => "<parameter>".to_string(),
+        Expr::Port(_) => "<port>".to_string(),
+        Expr::Values(vals) => {
+            for (i, v) in vals.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                fmt_expr(v, write, f)?;
             }
-            s.push(' ');
+            return Ok(());
         }
-        s.push_str(
-            if expr_is_nil(&last) {
-                "".to_string()
-            } else {
-                format!(" . {}", last)
-            }
-            .as_str(),
-        );
+    };
+    write!(f, "{}", s)
+}
 
-        s.push(')');
-        write!(f, "{}", s)
+fn fmt_prefixed(
+    prefix: &str,
+    expr: &Expr,
+    suffix: &str,
+    write: bool,
+    f: &mut Formatter<'_>,
+) -> Result {
+    write!(f, "{}", prefix)?;
+    fmt_expr(expr, write, f)?;
+    write!(f, "{}", suffix)
+}
+
+fn fmt_list(list: &List, write: bool, f: &mut Formatter<'_>) -> Result {
+    write!(f, "(")?;
+    let last = list.back().unwrap();
+    let len = list.len();
+    for (i, expr) in list.iter().take(len - 1).enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        fmt_expr(expr, write, f)?;
+    }
+    if !expr_is_nil(last) {
+        write!(f, " . ")?;
+        fmt_expr(last, write, f)?;
+    }
+    write!(f, ")")
+}
+
+// The message followed by the irritants
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", Displayed(&self.message))?;
+        for irritant in &self.irritants {
+            write!(f, " {}", irritant)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for PrintableList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        fmt_list(&self.0, true, f)
     }
 }
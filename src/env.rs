@@ -34,8 +34,8 @@ impl fmt::Debug for EnvType {
 pub struct Env(Rc<RefCell<EnvType>>);
 
 impl std::cmp::PartialEq for Env {
-    fn eq(&self, _: &Self) -> bool {
-        false
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
     }
 }
 
@@ -68,28 +68,18 @@ impl Env {
     }
 
     pub fn set(&self, key: &str, val: Expr) {
-        let env = self;
-
-        // search for the key in our list of maps
-        while !(*env.0).borrow().symbols.contains_key(key) {
-            let _env = match env.0.borrow().outer.as_ref() {
-                Some(env) => env,
+        match self.find_env(key) {
+            // once we find the frame the key is bound in, overwrite it there
+            Some(env) => env.insert(key, val),
 
-                // if we reach the end without finding it,
-                // insert the value in the first map
-                None => {
-                    self.insert(key, val);
-                    return;
-                }
-            };
+            // if we reach the end without finding it,
+            // insert the value in the first map
+            None => self.insert(key, val),
         }
-
-        // once we find it overwrite
-        env.insert(key, val);
     }
 
-    // Read only
-    fn find_env(&self, key: &str) -> Option<Env> {
+    /// The frame `key` is bound in, if any.
+    pub fn find_env(&self, key: &str) -> Option<Env> {
         let env = (*self.0).borrow();
         if env.symbols.contains_key(key) {
             Some(self.clone())
@@ -102,10 +92,7 @@ impl Env {
     }
 
     pub fn contains_symbol(&self, key: &str) -> bool {
-        match self.find_env(key) {
-            Some(_) => true,
-            _ => false,
-        }
+        self.find_env(key).is_some()
     }
 
     pub fn get(&self, key: &str) -> Result<Expr, UndefinedSymbol> {
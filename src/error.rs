@@ -0,0 +1,62 @@
+use crate::evaluator::error::EvalError;
+use crate::parser::error::ParserErr;
+use crate::tokenizer::error::TokenizeErr;
+use std::error;
+use std::fmt;
+
+// Any failure along the tokenize -> parse -> eval pipeline
+#[derive(Debug)]
+pub enum Error {
+    Tokenize(TokenizeErr),
+    Parse(ParserErr),
+    Eval(EvalError),
+}
+
+impl Error {
+    // Error message followed by the offending source, when it is known
+    pub fn report(&self, source: &str) -> String {
+        match &self {
+            Self::Tokenize(e) => format!("Tokenize error: {}", e.report(source)),
+            Self::Parse(e) => format!("Parse error: {}", e.report(source)),
+            Self::Eval(_) => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Self::Tokenize(e) => write!(f, "Tokenize error: {}", e),
+            Self::Parse(e) => write!(f, "Parse error: {}", e),
+            Self::Eval(e) => write!(f, "Eval error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self {
+            Self::Tokenize(e) => Some(e),
+            Self::Parse(e) => Some(e),
+            Self::Eval(e) => Some(e),
+        }
+    }
+}
+
+impl From<TokenizeErr> for Error {
+    fn from(e: TokenizeErr) -> Self {
+        Error::Tokenize(e)
+    }
+}
+
+impl From<ParserErr> for Error {
+    fn from(e: ParserErr) -> Self {
+        Error::Parse(e)
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(e: EvalError) -> Self {
+        Error::Eval(e)
+    }
+}
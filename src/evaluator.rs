@@ -1,11 +1,20 @@
 use super::env::Env;
-use super::parser::{Atom, Expr, Lambda, List, NativeEnc};
+use super::macros::{expand_all, uninterned_key};
+use super::parser::{Atom, Expr, Lambda, List, NativeEnc, SyntaxEnc};
+use crate::atom_str;
+use crate::exceptions;
+use crate::utils::{expr_is_nil, list_from, list_to_args, unshare, values_from};
 use error::EvalError;
-use std::convert::TryInto;
-use crate::expr_atom;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
 
 pub mod error {
-    use crate::parser::{Atom, Expr};
+    use super::{take_trace, CallSite};
+    use crate::parser::{Atom, Continuation, Expr, Node};
+    use std::error::Error;
+    use std::fmt;
+    use std::rc::Rc;
     #[derive(Debug)]
     pub enum EvalError {
         DottedList,
@@ -14,17 +23,235 @@ pub mod error {
         ExprTypeMismatch(String, Expr),
         EmptyList,
         WrongNumOfArgs(usize, usize),
+        ProcedureArity(String, String, usize),
+        DivisionByZero,
+        // The index and the length of what was indexed
+        IndexOutOfRange(usize, usize),
+        NotCallable(Expr),
+        BadSyntax(String),
+        // Something raised that no handler took care of
+        Raise(Box<Expr>),
+        // Unwinds up to the call/cc that captured the continuation
+        Escape(Rc<Continuation>, Box<Expr>),
+        ContinuationExpired,
     }
+
+    impl EvalError {
+        // Error message followed by the source of the call that failed,
+        // found by following the calls the error went through from the
+        // top-level form down. Undefined symbols point at the symbol.
+        pub fn report(&self, source: &str, form: &Node) -> String {
+            let trace = take_trace();
+            let mut node = form;
+            let mut pending: Option<usize> = None;
+            for (i, site) in trace.iter().rev().enumerate() {
+                let at = |n: &Node| site.at(n);
+                let found = match pending.and_then(|a| node.children.get(a)) {
+                    Some(child) => child.find(&at),
+                    None if i == 0 => node.find(&at),
+                    // A call inside the previous one
+                    None => node.children.iter().find_map(|c| c.find(&at)),
+                };
+                match found {
+                    Some(found) => {
+                        node = found;
+                        pending = site.failed_arg;
+                    }
+                    None => break,
+                }
+            }
+            if let Some(child) = pending.and_then(|a| node.children.get(a)) {
+                node = child;
+            }
+
+            if let EvalError::UndefinedSymbol(sym) = self {
+                let is_sym = |n: &Node| matches!(&n.atom, Some(Atom::Symbol(s)) if s == sym);
+                if let Some(found) = node.find(&is_sym).or_else(|| form.find(&is_sym)) {
+                    node = found;
+                }
+            }
+            let span = node.span;
+            format!("{}: {}\n{}", span.start, self, span.underline(source))
+        }
+    }
+
+    impl CallSite {
+        // Whether `node' is a call this site could have come from
+        fn at(&self, node: &Node) -> bool {
+            match node.children.first() {
+                Some(head) if node.atom.is_none() => {
+                    node.children.len() == self.argc + 1
+                        && (self.head.is_none() || head.atom == self.head)
+                }
+                _ => false,
+            }
+        }
+    }
+
+    impl fmt::Display for EvalError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match &self {
+                Self::DottedList => write!(f, "Expected a proper list but found a dotted one"),
+                Self::UndefinedSymbol(s) => write!(f, "Undefined symbol `{}'", s),
+                Self::TypeMismatch(t, a) => {
+                    write!(f, "Type mismatch, expected {} but found `{}'", t, a)
+                }
+                Self::ExprTypeMismatch(t, e) => {
+                    write!(f, "Type mismatch, expected {} but found `{}'", t, e)
+                }
+                Self::EmptyList => write!(f, "Unexpected empty list"),
+                Self::WrongNumOfArgs(expected, found) => write!(
+                    f,
+                    "Wrong number of arguments, expected {} but found {}",
+                    expected, found
+                ),
+                Self::ProcedureArity(name, expected, found) => write!(
+                    f,
+                    "Wrong number of arguments to `{}', expected {} but found {}",
+                    name, expected, found
+                ),
+                Self::DivisionByZero => write!(f, "Division by zero"),
+                Self::IndexOutOfRange(i, len) => {
+                    write!(f, "Index {} out of range for length {}", i, len)
+                }
+                Self::NotCallable(e) => write!(f, "`{}' is not callable", e),
+                Self::BadSyntax(s) => write!(f, "Bad syntax, {}", s),
+                Self::Raise(obj) => match &**obj {
+                    Expr::Condition(c) => write!(f, "Error, {}", c),
+                    obj => write!(f, "Uncaught exception `{}'", obj),
+                },
+                Self::Escape(_, val) => write!(f, "Escape to a continuation with `{}'", val),
+                Self::ContinuationExpired => write!(
+                    f,
+                    "Continuation called after its call/cc returned, only escaping is supported"
+                ),
+            }
+        }
+    }
+
+    impl Error for EvalError {}
+}
+
+/// Outcome of evaluating a form whose last step is in tail position.
+///
+/// Instead of recursing, special forms and lambda calls hand back the
+/// expression left to evaluate together with its environment, and
+/// `eval_expr` keeps looping over it. This way tail calls run in constant
+/// Rust stack space.
+pub enum Tail {
+    Return(Expr),
+    Eval(Expr, Env),
 }
 
 pub fn eval_expr(exp: Expr, env: &Env) -> Result<Expr, EvalError> {
+    let mut exp = exp;
+    let mut env = env.clone();
+    loop {
+        let tail = match exp {
+            Expr::Atom(box_atom) => {
+                return eval_atom(*box_atom, &env).map_err(|e| exceptions::raise_error(e, &env))
+            }
+            Expr::List(list) => eval_list(unshare(list), &env)?,
+            Expr::Quote(quo) => return Ok(*quo),
+            Expr::Lambda(_)
+            | Expr::CaseLambda(_)
+            | Expr::Macro(_)
+            | Expr::Continuation(_)
+            | Expr::Condition(_)
+            | Expr::Values(_)
+            | Expr::Promise(_)
+            | Expr::Parameter(_)
+            | Expr::Port(_) => return Ok(exp),
+            Expr::Quasiquote(q) => return eval_quasiquote(*q, 1, &env),
+            Expr::Unquote(_) | Expr::UnquoteSplicing(_) => {
+                return Err(EvalError::BadSyntax(
+                    "unquote outside quasiquote".to_string(),
+                ))
+            }
+        };
+        match tail {
+            Tail::Return(res) => return Ok(res),
+            Tail::Eval(next, next_env) => {
+                exp = next;
+                env = next_env;
+            }
+        }
+    }
+}
+
+// Turns `(unquote x)' and friends into the expressions the reader
+// builds for `,x', so both spellings behave the same
+fn quasi_form(list: &List) -> Option<Expr> {
+    let mut iter = list.iter();
+    let head = match iter.next() {
+        Some(Expr::Atom(a)) => match &**a {
+            Atom::Symbol(s) => s.as_str(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let arg = Box::new(iter.next()?.clone());
+    if list.len() != 3 || !expr_is_nil(iter.next()?) {
+        return None;
+    }
+    match head {
+        "quasiquote" => Some(Expr::Quasiquote(arg)),
+        "unquote" => Some(Expr::Unquote(arg)),
+        "unquote-splicing" => Some(Expr::UnquoteSplicing(arg)),
+        _ => None,
+    }
+}
+
+// Builds the template of a quasiquote, evaluating what is unquoted at
+// `depth` 1. Nested quasiquotes go one level deeper and unquotes one up.
+pub fn eval_quasiquote(exp: Expr, depth: usize, env: &Env) -> Result<Expr, EvalError> {
     match exp {
-        Expr::Atom(box_atom) => eval_atom(*box_atom, env),
-        Expr::List(list) => eval_list(*list, env),
-        Expr::Quote(quo) => Ok(*quo),
-        // TODO: match against all Expr types
-        Expr::Lambda(lambda) => Ok(Expr::Lambda(lambda)),
-        _ => todo!("more types"),
+        Expr::Unquote(u) if depth == 1 => eval_expr(*u, env),
+        Expr::Unquote(u) => Ok(Expr::Unquote(Box::new(eval_quasiquote(
+            *u,
+            depth - 1,
+            env,
+        )?))),
+        Expr::UnquoteSplicing(_) if depth == 1 => Err(EvalError::BadSyntax(
+            "unquote-splicing outside list".to_string(),
+        )),
+        Expr::UnquoteSplicing(u) => Ok(Expr::UnquoteSplicing(Box::new(eval_quasiquote(
+            *u,
+            depth - 1,
+            env,
+        )?))),
+        Expr::Quasiquote(q) => Ok(Expr::Quasiquote(Box::new(eval_quasiquote(
+            *q,
+            depth + 1,
+            env,
+        )?))),
+        Expr::Quote(q) => Ok(Expr::Quote(Box::new(eval_quasiquote(*q, depth, env)?))),
+        Expr::List(list) => {
+            if let Some(form) = quasi_form(&list) {
+                return eval_quasiquote(form, depth, env);
+            }
+
+            let mut list = unshare(list);
+            let tail = list.pop_back().ok_or(EvalError::EmptyList)?;
+            let mut res = List::new();
+            for exp in list {
+                match exp {
+                    Expr::UnquoteSplicing(u) if depth == 1 => {
+                        res.append(&mut list_to_args(eval_expr(*u, env)?)?);
+                    }
+                    exp => res.push_back(eval_quasiquote(exp, depth, env)?),
+                }
+            }
+
+            // The tail may have been unquoted into a list, as in `(1 . ,xs)'
+            match eval_quasiquote(tail, depth, env)? {
+                Expr::List(l) => res.append(&mut unshare(l)),
+                nil if expr_is_nil(&nil) && res.is_empty() => return Ok(nil),
+                tail => res.push_back(tail),
+            }
+            Ok(Expr::List(Rc::new(res)))
+        }
+        other => Ok(other),
     }
 }
 
@@ -34,68 +261,222 @@ pub fn eval_atom(atom: Atom, env: &Env) -> Result<Expr, EvalError> {
             let expr = env.get(&s);
             expr.map_err(|_| EvalError::UndefinedSymbol(s))
         }
+        Atom::Uninterned(s) => env
+            .get(&uninterned_key(&s))
+            .map_err(|_| EvalError::UndefinedSymbol(s)),
+        // A literal is read once but may be evaluated many times, each
+        // evaluation gets its own copy so mutating one leaves the source as is
+        Atom::Str(s) => Ok(atom_str!(s.borrow().clone())),
         other => Ok(Expr::Atom(Box::new(other))),
     }
 }
 
+/// Evaluates a top-level form, expanding the macro uses in it first,
+/// those in its lambda bodies included, so they are expanded only once.
+pub fn eval_form(exp: Expr, env: &Env) -> Result<Expr, EvalError> {
+    clear_trace();
+    let exp = expand_all(exp, &HashSet::new(), env)?;
+    eval_expr(exp, env)
+}
+
 pub fn eval(mut list: List, env: &Env) -> Result<Expr, EvalError> {
-    match list.pop_back() {
-        Some(Expr::Atom(nil)) => {
-            if *nil != Atom::Nil {
-                return Err(EvalError::DottedList);
-            }
-        }
-        Some(_) => return Err(EvalError::DottedList),
-        None => return Err(EvalError::EmptyList),
-    };
     if list.len() != 1 {
         Err(EvalError::WrongNumOfArgs(1, list.len()))
     } else {
-        let res = eval_expr(list.pop_front().unwrap(), env)?;
-        Ok(eval_expr(res, env)?)
+        let exp = expand_all(list.pop_front().unwrap(), &HashSet::new(), env)?;
+        eval_expr(exp, env)
     }
 }
 
-pub fn eval_list(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+/// A call an error went through on its way up, innermost first.
+///
+/// Used to find the subexpression of the top-level form that failed,
+/// by the head of the call and how many operands it has.
+#[derive(Debug)]
+pub(crate) struct CallSite {
+    pub head: Option<Atom>,
+    pub argc: usize,
+    // Index in the call of the operand whose evaluation failed,
+    // 0 being the head itself
+    pub failed_arg: Option<usize>,
+}
+
+thread_local! {
+    static TRACE: RefCell<Vec<CallSite>> = const { RefCell::new(Vec::new()) };
+}
+
+// Forgets the calls of an error that got handled
+pub(crate) fn take_trace() -> Vec<CallSite> {
+    TRACE.with(|t| t.take())
+}
+
+pub(crate) fn set_trace(trace: Vec<CallSite>) {
+    TRACE.with(|t| *t.borrow_mut() = trace)
+}
+
+pub(crate) fn trace_len() -> usize {
+    TRACE.with(|t| t.borrow().len())
+}
+
+// Forgets the calls traced since there were `len`, those of an error
+// caught before reaching the top level
+pub(crate) fn truncate_trace(len: usize) {
+    TRACE.with(|t| t.borrow_mut().truncate(len))
+}
+
+// Forgets the calls of every error that was not reported, so they don't
+// point the report of the next one at the wrong place
+fn clear_trace() {
+    TRACE.with(|t| t.borrow_mut().clear())
+}
+
+fn trace<T>(
+    res: Result<T, EvalError>,
+    head: &Option<Atom>,
+    argc: usize,
+    failed_arg: Option<usize>,
+    env: &Env,
+) -> Result<T, EvalError> {
+    // The first call an error goes through is where it happened
+    let res = res.map_err(|e| exceptions::raise_error(e, env));
+    if let Err(e) = &res {
+        // Escapes are how continuations return, not errors
+        if !matches!(e, EvalError::Escape(..)) {
+            let site = CallSite {
+                head: head.clone(),
+                argc,
+                failed_arg,
+            };
+            TRACE.with(|t| t.borrow_mut().push(site));
+        }
+    }
+    res
+}
+
+pub fn eval_list(mut list: List, env: &Env) -> Result<Tail, EvalError> {
     let first = list.pop_front().ok_or(EvalError::EmptyList)?;
+    let head = match &first {
+        Expr::Atom(a) => Some((**a).clone()),
+        _ => None,
+    };
+    let argc = list.len().saturating_sub(1);
+
+    let f = trace(eval_expr(first, env), &head, argc, Some(0), env)?;
 
-    match eval_expr(first, env)? {
-        Expr::Atom(a) => match *a {
-            Atom::Native(NativeEnc(f)) => Ok(f(list, env)?),
-            other => Ok(expr_atom!(other)),
+    // Special forms decide themselves what to evaluate
+    if let Expr::Atom(a) = &f {
+        if let Atom::Syntax(SyntaxEnc(form)) = **a {
+            return trace(form(list, env), &head, argc, None, env);
+        }
+    }
+    // and macro uses left by the expansion pass, as those of a macro
+    // defined after the form, are expanded before being evaluated
+    if let Expr::Macro(m) = &f {
+        list.push_front(f.clone());
+        let form = Expr::List(Rc::new(list));
+        let expansion = trace(m.expand(form, &HashSet::new(), env), &head, argc, None, env)?;
+        return Ok(Tail::Eval(expansion, env.clone()));
+    }
+
+    match list.pop_back() {
+        Some(Expr::Atom(nil)) if *nil == Atom::Nil => {}
+        Some(_) => return trace(Err(EvalError::DottedList), &head, argc, None, env),
+        None => return Err(EvalError::EmptyList),
+    }
+    let mut args = List::new();
+    for (i, e) in list.into_iter().enumerate() {
+        args.push_back(trace(eval_expr(e, env), &head, argc, Some(i + 1), env)?);
+    }
+    trace(apply_tail(&f, args, env), &head, argc, None, env)
+}
+
+// Calls a procedure with already evaluated arguments,
+// the body of a lambda is left in tail position
+pub fn apply_tail(f: &Expr, args: List, env: &Env) -> Result<Tail, EvalError> {
+    match f {
+        Expr::Atom(a) => match &**a {
+            Atom::Native(NativeEnc(f)) => Ok(Tail::Return(f(args, env)?)),
+            _ => Err(EvalError::NotCallable(f.clone())),
+        },
+        Expr::Lambda(l) => eval_lambda(l, args),
+        Expr::Parameter(p) => match args.len() {
+            0 => Ok(Tail::Return(p.value.borrow().clone())),
+            n => Err(EvalError::WrongNumOfArgs(0, n)),
         },
-        Expr::Lambda(l) => eval_lambda(*l, list, env),
-        _ => todo!("check more expressions"),
+        Expr::Continuation(k) => {
+            if !k.active.get() {
+                return Err(EvalError::ContinuationExpired);
+            }
+            Err(EvalError::Escape(k.clone(), Box::new(values_from(args))))
+        }
+        Expr::CaseLambda(clauses) => match clauses.iter().find(|l| l.accepts(args.len())) {
+            Some(l) => eval_lambda(l, args),
+            None => Err(EvalError::ProcedureArity(
+                f.to_string(),
+                clauses
+                    .iter()
+                    .map(|l| l.arity())
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+                args.len(),
+            )),
+        },
+        _ => Err(EvalError::NotCallable(f.clone())),
+    }
+}
+
+// Calls a procedure from native code, such as map or apply
+pub fn apply(f: &Expr, args: List, env: &Env) -> Result<Expr, EvalError> {
+    match apply_tail(f, args, env)? {
+        Tail::Return(res) => Ok(res),
+        Tail::Eval(exp, env) => eval_expr(exp, &env),
     }
 }
 
-pub fn eval_lambda(lambda: Lambda, mut args: List, env: &Env) -> Result<Expr, EvalError> {
-    args.pop_back().unwrap();
-    let lambda_args_count = lambda.args_list.len();
-    let args_count = args.len();
+pub fn eval_lambda(lambda: &Rc<Lambda>, args: List) -> Result<Tail, EvalError> {
+    // Every call gets its own frame on top of the captured environment
+    let frame = Env::new(Some(lambda.env.clone()));
+    bind_args(lambda, args, &frame)?;
+    eval_body(lambda.body.clone(), &frame)
+}
 
-    if args_count != lambda_args_count {
-        return Err(EvalError::WrongNumOfArgs(
-            lambda_args_count.try_into().unwrap(),
-            args_count,
+/// Binds `args` to the formals of `lambda` in `frame`.
+pub fn bind_args(lambda: &Rc<Lambda>, args: List, frame: &Env) -> Result<(), EvalError> {
+    if !lambda.accepts(args.len()) {
+        return Err(EvalError::ProcedureArity(
+            Expr::Lambda(lambda.clone()).to_string(),
+            lambda.arity(),
+            args.len(),
         ));
     }
 
-    for (val, syn) in args.iter().zip(lambda.args_list.iter()) {
-        let expr = eval_expr(val.clone(), env)?;
-        lambda.env.insert(syn, expr);
+    let mut args = args.into_iter();
+    for syn in &lambda.args_list {
+        frame.insert(syn, args.next().unwrap());
     }
-
-    // TODO: eval all the list
-    eval_body(lambda.body, &lambda.env)
+    // Defaults are evaluated in the frame, so they can use earlier arguments
+    for (syn, default) in &lambda.optionals {
+        let val = match args.next() {
+            Some(val) => val,
+            None => eval_expr(default.clone(), frame)?,
+        };
+        frame.insert(syn, val);
+    }
+    if let Some(rest) = &lambda.rest {
+        frame.insert(rest, list_from(args.collect()));
+    }
+    Ok(())
 }
 
-fn eval_body(mut body: List, env: &Env) -> Result<Expr, EvalError> {
-    assert!(!body.is_empty(), "body of the lambda cannot be empty.");
-    let last = body.pop_back().unwrap();
+/// Evaluates every expression of `body` but the last one, which is left
+/// to the caller in tail position.
+pub fn eval_body(mut body: List, env: &Env) -> Result<Tail, EvalError> {
+    let last = body
+        .pop_back()
+        .ok_or_else(|| EvalError::BadSyntax("body cannot be empty".to_string()))?;
     for expr in body {
         eval_expr(expr, env)?;
     }
 
-    eval_expr(last, env)
+    Ok(Tail::Eval(last, env.clone()))
 }
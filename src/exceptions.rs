@@ -0,0 +1,90 @@
+use super::env::Env;
+use super::evaluator::apply;
+use super::evaluator::error::EvalError;
+use super::parser::{Condition, Expr, List};
+use crate::atom_str;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// An entry of the stack of installed exception handlers.
+pub enum Handler {
+    // Installed by with-exception-handler, called where the raise happens
+    Procedure(Expr),
+    // Installed by guard, which catches what is raised by unwinding to it
+    Guard,
+}
+
+thread_local! {
+    static HANDLERS: RefCell<Vec<Handler>> = const { RefCell::new(vec![]) };
+}
+
+/// Runs `f` with `handler` installed, removing it however `f` is left.
+pub fn with_handler<T>(handler: Handler, f: impl FnOnce() -> T) -> T {
+    let depth = HANDLERS.with(|h| {
+        let mut h = h.borrow_mut();
+        h.push(handler);
+        h.len() - 1
+    });
+    let res = f();
+    HANDLERS.with(|h| h.borrow_mut().truncate(depth));
+    res
+}
+
+pub fn make_condition(message: &str, irritants: List) -> Expr {
+    Expr::Condition(Rc::new(Condition {
+        message: atom_str!(message.to_string()),
+        irritants,
+    }))
+}
+
+/// The object a guard or handler sees for `err`, built-in errors become
+/// condition objects. Escapes to continuations can't be caught.
+pub fn condition_of(err: &EvalError) -> Option<Expr> {
+    match err {
+        EvalError::Raise(obj) => Some((**obj).clone()),
+        EvalError::Escape(..) => None,
+        e => Some(make_condition(&e.to_string(), List::new())),
+    }
+}
+
+/// Raises `obj` to the innermost handler, which runs with the outer ones
+/// installed. Non-continuable raises must not return from their handler.
+pub fn raise(obj: Expr, continuable: bool, env: &Env) -> Result<Expr, EvalError> {
+    let top = HANDLERS.with(|h| h.borrow_mut().pop());
+    let res = match &top {
+        Some(Handler::Procedure(handler)) => match apply(handler, List::from([obj.clone()]), env) {
+            Ok(val) if continuable => Ok(val),
+            Ok(_) => raise(
+                make_condition("exception handler returned", List::from([obj])),
+                false,
+                env,
+            ),
+            Err(e) => Err(e),
+        },
+        // Guards, or the top level, catch it once it unwinds to them
+        _ => Err(EvalError::Raise(Box::new(obj))),
+    };
+    if let Some(top) = top {
+        HANDLERS.with(|h| h.borrow_mut().push(top));
+    }
+    res
+}
+
+/// Hands a built-in error to the innermost handler where it happens, when
+/// that handler was installed by with-exception-handler, so it runs in the
+/// dynamic context of the error as it would for a raise. Returns the error
+/// to keep unwinding with.
+pub fn raise_error(err: EvalError, env: &Env) -> EvalError {
+    if matches!(err, EvalError::Raise(_) | EvalError::Escape(..)) {
+        return err;
+    }
+    let handled = HANDLERS.with(|h| matches!(h.borrow().last(), Some(Handler::Procedure(_))));
+    if !handled {
+        return err;
+    }
+    let obj = condition_of(&err).unwrap();
+    match raise(obj, false, env) {
+        Err(e) => e,
+        Ok(_) => unreachable!("non-continuable raises don't return"),
+    }
+}
@@ -1,9 +1,17 @@
 pub mod display;
 pub mod env;
+pub mod error;
 pub mod evaluator;
+pub mod exceptions;
+pub mod macros;
 pub mod native;
+pub mod number;
 pub mod parser;
+pub mod port;
+pub mod promise;
 #[cfg(test)]
 mod tests;
 pub mod tokenizer;
 pub mod utils;
+
+pub use error::Error;
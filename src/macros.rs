@@ -0,0 +1,786 @@
+use super::env::Env;
+use super::evaluator::apply;
+use super::evaluator::error::EvalError;
+use super::parser::{Atom, Expr, Lambda, List};
+use crate::atom_nil;
+use crate::utils::{expr_is_nil, is_equal, list_to_args, unshare};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// A macro, which rewrites the forms it heads before they are evaluated.
+#[derive(Debug)]
+pub enum Macro {
+    Rules(SyntaxRules),
+    // A procedure called with the unevaluated operands, as with define-macro
+    Procedural(Expr),
+}
+
+// Macros are shared through an Rc, so two of them are equal
+// only when they are the very same transformer
+impl std::cmp::PartialEq for Macro {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Macro {
+    /// Expands `form` a single time, where `env` is the environment of
+    /// the form and `bound` the variables around it not yet in `env`.
+    pub fn expand(
+        &self,
+        form: Expr,
+        bound: &HashSet<String>,
+        env: &Env,
+    ) -> Result<Expr, EvalError> {
+        match self {
+            Macro::Rules(rules) => rules.expand(form, bound, env),
+            Macro::Procedural(transformer) => {
+                let mut operands = list_to_args(form)?;
+                operands.pop_front();
+                apply(transformer, operands, env)
+            }
+        }
+    }
+}
+
+/// A `syntax-rules` transformer.
+///
+/// Each rule pairs a pattern with the template its matches are rewritten
+/// into. The first element of a pattern stands for the macro keyword and is
+/// never looked at.
+#[derive(Debug)]
+pub struct SyntaxRules {
+    pub ellipsis: String,
+    pub literals: Vec<String>,
+    pub rules: Vec<(Expr, Expr)>,
+    // Where the macro was defined, the free identifiers of the templates
+    // refer to what they are bound to there
+    pub env: Env,
+}
+
+// What a pattern variable matched, nested once per ellipsis it is under
+#[derive(Debug, Clone)]
+enum Binding {
+    One(Expr),
+    Many(Vec<Bindings>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+// Fresh names given to the variables a template binds
+type Renames = HashMap<String, String>;
+
+// What renaming a template needs besides the renames in scope
+struct Context<'a> {
+    bindings: &'a Bindings,
+    // The variables around the use of the macro and its environment
+    bound: &'a HashSet<String>,
+    env: &'a Env,
+}
+
+thread_local! {
+    static SYMBOLS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A symbol name that was never used before, made out of `prefix`.
+pub fn gensym(prefix: &str) -> String {
+    let n = SYMBOLS.with(|c| {
+        c.set(c.get() + 1);
+        c.get()
+    });
+    format!("{}.{}", prefix, n)
+}
+
+/// The name an uninterned symbol is bound under. The reader never puts
+/// a space in a symbol, so no symbol read can be bound under it.
+pub fn uninterned_key(name: &str) -> String {
+    format!(" {}", name)
+}
+
+fn symbol_expr(name: &str) -> Expr {
+    Expr::Atom(Box::new(Atom::Symbol(name.to_string())))
+}
+
+fn symbol(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Atom(a) => match &**a {
+            Atom::Symbol(s) => Some(s.as_str()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Splits a list into its elements and its tail, `()' for proper lists.
+// Anything else is an empty list with itself as the tail.
+fn split_list(expr: Expr) -> (Vec<Expr>, Expr) {
+    match expr {
+        Expr::List(l) => {
+            let mut items: Vec<Expr> = unshare(l).into_iter().collect();
+            let tail = items.pop().unwrap_or(atom_nil!());
+            (items, tail)
+        }
+        other => (vec![], other),
+    }
+}
+
+fn join_list(mut items: Vec<Expr>, tail: Expr) -> Expr {
+    match tail {
+        // `(a . (b c))' is the same list as `(a b c)'
+        Expr::List(l) => items.extend(unshare(l)),
+        nil if expr_is_nil(&nil) && items.is_empty() => return nil,
+        tail => items.push(tail),
+    }
+    Expr::List(Rc::new(items.into_iter().collect()))
+}
+
+impl SyntaxRules {
+    /// Rewrites `form` with the first rule whose pattern matches it.
+    pub fn expand(
+        &self,
+        form: Expr,
+        bound: &HashSet<String>,
+        env: &Env,
+    ) -> Result<Expr, EvalError> {
+        let (mut items, tail) = split_list(form);
+        if !items.is_empty() {
+            items.remove(0);
+        }
+        let form = join_list(items, tail);
+
+        for (pattern, template) in &self.rules {
+            let (mut pattern, tail) = split_list(pattern.clone());
+            if !pattern.is_empty() {
+                pattern.remove(0);
+            }
+            let pattern = join_list(pattern, tail);
+
+            let mut bindings = Bindings::new();
+            if self.matches(&pattern, form.clone(), &mut bindings) {
+                let cx = Context {
+                    bindings: &bindings,
+                    bound,
+                    env,
+                };
+                let template = self.rename_binders(template.clone(), &cx);
+                return self.instantiate(&template, &bindings);
+            }
+        }
+        Err(EvalError::BadSyntax(format!(
+            "no syntax rule matches `{}'",
+            form
+        )))
+    }
+
+    fn is_ellipsis(&self, expr: &Expr) -> bool {
+        symbol(expr) == Some(self.ellipsis.as_str())
+    }
+
+    fn matches(&self, pattern: &Expr, form: Expr, bindings: &mut Bindings) -> bool {
+        if let Some(s) = symbol(pattern) {
+            if self.literals.iter().any(|l| l == s) {
+                return symbol(&form) == Some(s);
+            }
+            if s != "_" {
+                bindings.insert(s.to_string(), Binding::One(form));
+            }
+            return true;
+        }
+
+        let patterns: Vec<&Expr> = match pattern {
+            Expr::List(l) => l.iter().collect(),
+            _ => return is_equal(pattern, &form),
+        };
+        let (tail_pattern, patterns) = match patterns.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        let (items, tail) = split_list(form);
+
+        let ellipsis = patterns.iter().position(|p| self.is_ellipsis(p));
+        let (before, repeated, after) = match ellipsis {
+            Some(i) if i > 0 => (
+                &patterns[..i - 1],
+                Some(patterns[i - 1]),
+                &patterns[i + 1..],
+            ),
+            _ => (patterns, None, &patterns[0..0]),
+        };
+
+        let fixed = before.len() + after.len();
+        let proper = expr_is_nil(tail_pattern);
+        if items.len() < fixed || (proper && repeated.is_none() && items.len() != fixed) {
+            return false;
+        }
+
+        let mut items = items.into_iter();
+        for p in before {
+            if !self.matches(p, items.next().unwrap(), bindings) {
+                return false;
+            }
+        }
+
+        if let Some(repeated) = repeated {
+            let count = items.len() - after.len();
+            let mut matched = vec![];
+            for item in items.by_ref().take(count) {
+                let mut inner = Bindings::new();
+                if !self.matches(repeated, item, &mut inner) {
+                    return false;
+                }
+                matched.push(inner);
+            }
+            for var in self.pattern_vars(repeated) {
+                let seq = matched
+                    .iter()
+                    .map(|b| match b.get(&var) {
+                        Some(Binding::Many(m)) => {
+                            Bindings::from([(var.clone(), Binding::Many(m.clone()))])
+                        }
+                        Some(one) => Bindings::from([(var.clone(), one.clone())]),
+                        None => Bindings::new(),
+                    })
+                    .collect();
+                bindings.insert(var, Binding::Many(seq));
+            }
+            for p in after {
+                if !self.matches(p, items.next().unwrap(), bindings) {
+                    return false;
+                }
+            }
+            return self.matches(tail_pattern, tail, bindings);
+        }
+
+        // Whatever is left over matches the tail of the pattern
+        let rest = join_list(items.collect(), tail);
+        self.matches(tail_pattern, rest, bindings)
+    }
+
+    fn pattern_vars(&self, pattern: &Expr) -> Vec<String> {
+        match pattern {
+            Expr::List(l) => l.iter().flat_map(|p| self.pattern_vars(p)).collect(),
+            _ => match symbol(pattern) {
+                Some(s)
+                    if s != "_" && s != self.ellipsis && !self.literals.iter().any(|l| l == s) =>
+                {
+                    vec![s.to_string()]
+                }
+                _ => vec![],
+            },
+        }
+    }
+
+    fn instantiate(&self, template: &Expr, bindings: &Bindings) -> Result<Expr, EvalError> {
+        match template {
+            Expr::Atom(_) => match symbol(template).and_then(|s| bindings.get(s)) {
+                Some(Binding::One(e)) => Ok(e.clone()),
+                Some(Binding::Many(_)) => Err(EvalError::BadSyntax(format!(
+                    "missing ellipsis after `{}' in template",
+                    template
+                ))),
+                None => Ok(template.clone()),
+            },
+            Expr::Quote(q) => Ok(Expr::Quote(Box::new(self.instantiate(q, bindings)?))),
+            Expr::Quasiquote(q) => Ok(Expr::Quasiquote(Box::new(self.instantiate(q, bindings)?))),
+            Expr::Unquote(u) => Ok(Expr::Unquote(Box::new(self.instantiate(u, bindings)?))),
+            Expr::UnquoteSplicing(u) => Ok(Expr::UnquoteSplicing(Box::new(
+                self.instantiate(u, bindings)?,
+            ))),
+            Expr::List(l) => {
+                let (items, tail) = split_list(Expr::List(l.clone()));
+
+                // (... template) escapes the ellipsis
+                if items.len() == 2 && expr_is_nil(&tail) && self.is_ellipsis(&items[0]) {
+                    let escaped = SyntaxRules {
+                        ellipsis: String::new(),
+                        literals: self.literals.clone(),
+                        rules: vec![],
+                        env: self.env.clone(),
+                    };
+                    return escaped.instantiate(&items[1], bindings);
+                }
+
+                let mut res = vec![];
+                let mut items = items.into_iter().peekable();
+                while let Some(item) = items.next() {
+                    let mut depth = 0;
+                    while items.peek().is_some_and(|i| self.is_ellipsis(i)) {
+                        items.next();
+                        depth += 1;
+                    }
+                    if depth == 0 {
+                        res.push(self.instantiate(&item, bindings)?);
+                    } else {
+                        self.instantiate_repeated(&item, bindings, depth, &mut res)?;
+                    }
+                }
+                let tail = self.instantiate(&tail, bindings)?;
+                Ok(join_list(res, tail))
+            }
+            other => Ok(other.clone()),
+        }
+    }
+
+    // Instantiates `template` once per match of the variables under the
+    // ellipsis, `depth` ellipses deep
+    fn instantiate_repeated(
+        &self,
+        template: &Expr,
+        bindings: &Bindings,
+        depth: usize,
+        res: &mut Vec<Expr>,
+    ) -> Result<(), EvalError> {
+        if depth == 0 {
+            res.push(self.instantiate(template, bindings)?);
+            return Ok(());
+        }
+
+        let vars: Vec<_> = self
+            .pattern_vars(template)
+            .into_iter()
+            .filter(|v| matches!(bindings.get(v), Some(Binding::Many(_))))
+            .collect();
+        let seqs: Vec<_> = vars
+            .iter()
+            .map(|v| match bindings.get(v) {
+                Some(Binding::Many(m)) => m,
+                _ => unreachable!(),
+            })
+            .collect();
+        let count = match seqs.first() {
+            Some(seq) => seq.len(),
+            None => {
+                return Err(EvalError::BadSyntax(format!(
+                    "no pattern variable before ellipsis in `{}'",
+                    template
+                )))
+            }
+        };
+        if seqs.iter().any(|s| s.len() != count) {
+            return Err(EvalError::BadSyntax(
+                "pattern variables matched different lengths".to_string(),
+            ));
+        }
+
+        for i in 0..count {
+            let mut inner = bindings.clone();
+            for seq in &seqs {
+                inner.extend(seq[i].clone());
+            }
+            self.instantiate_repeated(template, &inner, depth - 1, res)?;
+        }
+        Ok(())
+    }
+
+    // Hygiene: variables the template itself binds, through lambda or the
+    // let family, get a fresh name on each expansion so they can't capture
+    // the ones written by the user of the macro. A variable is renamed
+    // only within the form that binds it, elsewhere it is free, and if a
+    // binding around the use would capture it, it is resolved to what it
+    // is bound to where the macro was defined.
+    fn rename_binders(&self, template: Expr, cx: &Context) -> Expr {
+        self.rename(template, &Renames::new(), cx)
+    }
+
+    // `renames` along with fresh names for `binders`, but not for the
+    // pattern variables, which stand for what the user wrote
+    fn scope(&self, binders: HashSet<String>, renames: &Renames, cx: &Context) -> Renames {
+        let mut scope = renames.clone();
+        for s in binders {
+            if !cx.bindings.contains_key(&s) && s != self.ellipsis && s != "_" {
+                let renamed = gensym(&s);
+                scope.insert(s, renamed);
+            }
+        }
+        scope
+    }
+
+    // Where the macro was defined, the frame of the free identifier `name`,
+    // if something else is bound to it at the use of the macro
+    fn captured(&self, name: &str, cx: &Context) -> Option<Env> {
+        if cx.bindings.contains_key(name) || name == self.ellipsis {
+            return None;
+        }
+        let defined = self.env.find_env(name)?;
+        if !cx.bound.contains(name) && cx.env.find_env(name).as_ref() == Some(&defined) {
+            return None;
+        }
+        Some(defined)
+    }
+
+    // What a captured free identifier stands for. Special forms, macros and
+    // built-in procedures take its place, other variables are read through
+    // a closure made where the macro was defined, so changes to them are seen.
+    fn resolve(&self, name: &str, cx: &Context) -> Option<Expr> {
+        match self.captured(name, cx)?.get(name).ok()? {
+            Expr::Atom(a) if matches!(*a, Atom::Syntax(_) | Atom::Native(_)) => Some(Expr::Atom(a)),
+            m @ Expr::Macro(_) => Some(m),
+            _ => Some(self.call_there(vec![], symbol_expr(name), vec![])),
+        }
+    }
+
+    // A call with `args` to a closure over the environment of the macro
+    fn call_there(&self, formals: Vec<String>, body: Expr, args: Vec<Expr>) -> Expr {
+        let closure = Lambda {
+            name: None,
+            args_list: formals,
+            optionals: vec![],
+            rest: None,
+            body: List::from([body]),
+            env: self.env.clone(),
+        };
+        let mut call = vec![Expr::Lambda(Rc::new(closure))];
+        call.extend(args);
+        join_list(call, atom_nil!())
+    }
+
+    fn rename(&self, template: Expr, renames: &Renames, cx: &Context) -> Expr {
+        let list = match template {
+            Expr::Atom(a) => {
+                return match *a {
+                    Atom::Symbol(s) => match renames.get(&s) {
+                        Some(r) => Expr::Atom(Box::new(Atom::Uninterned(r.clone()))),
+                        None => self
+                            .resolve(&s, cx)
+                            .unwrap_or_else(|| Expr::Atom(Box::new(Atom::Symbol(s)))),
+                    },
+                    a => Expr::Atom(Box::new(a)),
+                }
+            }
+            Expr::List(l) => Expr::List(l),
+            Expr::Quasiquote(q) => {
+                return Expr::Quasiquote(Box::new(self.rename_quasi(*q, 1, renames, cx)))
+            }
+            // Quoted symbols are data, not variables
+            other => return other,
+        };
+
+        let (items, tail) = split_list(list);
+        let keyword = match items.first().and_then(symbol) {
+            Some(k) if !renames.contains_key(k) => k.to_string(),
+            _ => String::new(),
+        };
+        let items = match keyword.as_str() {
+            "lambda" if items.len() > 1 => {
+                let mut binders = HashSet::new();
+                push_formals(&items[1], &mut binders);
+                let scope = self.scope(binders, renames, cx);
+                self.rename_all(items, &scope, cx)
+            }
+            "case-lambda" => {
+                let mut items = items.into_iter();
+                let mut res: Vec<Expr> = items.next().into_iter().collect();
+                // Each clause binds its own formals
+                for clause in items {
+                    let mut binders = HashSet::new();
+                    if let Expr::List(c) = &clause {
+                        if let Some(formals) = c.front() {
+                            push_formals(formals, &mut binders);
+                        }
+                    }
+                    let scope = self.scope(binders, renames, cx);
+                    res.push(self.rename(clause, &scope, cx));
+                }
+                res
+            }
+            "let" | "let*" | "letrec" | "letrec*" => self.rename_let(items, renames, cx),
+            // A captured variable is assigned where the macro was defined
+            "set!" if items.len() == 3 => match symbol(&items[1]) {
+                Some(var) if !renames.contains_key(var) && self.captured(var, cx).is_some() => {
+                    let value = gensym("value");
+                    let mut items = items.into_iter();
+                    let assign = vec![
+                        items.next().unwrap(),
+                        items.next().unwrap(),
+                        Expr::Atom(Box::new(Atom::Uninterned(value.clone()))),
+                    ];
+                    let args = self.rename_all(items.collect(), renames, cx);
+                    let body = join_list(assign, atom_nil!());
+                    return self.call_there(vec![uninterned_key(&value)], body, args);
+                }
+                _ => self.rename_all(items, renames, cx),
+            },
+            _ => self.rename_all(items, renames, cx),
+        };
+        let tail = self.rename(tail, renames, cx);
+        join_list(items, tail)
+    }
+
+    // Only what is unquoted in a quasiquote, `depth` levels deep, is code
+    fn rename_quasi(&self, template: Expr, depth: usize, renames: &Renames, cx: &Context) -> Expr {
+        match template {
+            Expr::Unquote(u) if depth == 1 => Expr::Unquote(Box::new(self.rename(*u, renames, cx))),
+            Expr::UnquoteSplicing(u) if depth == 1 => {
+                Expr::UnquoteSplicing(Box::new(self.rename(*u, renames, cx)))
+            }
+            Expr::Unquote(u) => {
+                Expr::Unquote(Box::new(self.rename_quasi(*u, depth - 1, renames, cx)))
+            }
+            Expr::UnquoteSplicing(u) => {
+                Expr::UnquoteSplicing(Box::new(self.rename_quasi(*u, depth - 1, renames, cx)))
+            }
+            Expr::Quasiquote(q) => {
+                Expr::Quasiquote(Box::new(self.rename_quasi(*q, depth + 1, renames, cx)))
+            }
+            Expr::List(l) => Expr::List(Rc::new(
+                unshare(l)
+                    .into_iter()
+                    .map(|e| self.rename_quasi(e, depth, renames, cx))
+                    .collect(),
+            )),
+            other => other,
+        }
+    }
+
+    fn rename_all(&self, items: Vec<Expr>, renames: &Renames, cx: &Context) -> Vec<Expr> {
+        items
+            .into_iter()
+            .map(|e| self.rename(e, renames, cx))
+            .collect()
+    }
+
+    // The bound variables are seen by the body, and by the initial values
+    // as well for letrec, or by the ones after them for let*. The name of
+    // a named let is only seen by the body.
+    fn rename_let(&self, items: Vec<Expr>, renames: &Renames, cx: &Context) -> Vec<Expr> {
+        let mut items = items.into_iter();
+        let keyword = items.next().unwrap();
+        let kind = symbol(&keyword).unwrap_or_default().to_string();
+        let mut res = vec![keyword];
+
+        let mut outer = renames.clone();
+        if kind == "let" {
+            if let Some(name) = items.as_slice().first().and_then(symbol) {
+                let name = HashSet::from([name.to_string()]);
+                outer = self.scope(name, renames, cx);
+                res.push(self.rename(items.next().unwrap(), &outer, cx));
+            }
+        }
+
+        let (specs, specs_tail) = match items.next() {
+            Some(specs) => split_list(specs),
+            None => return res,
+        };
+        let names: Vec<Option<String>> = specs
+            .iter()
+            .map(|spec| match spec {
+                Expr::List(b) => b.front().and_then(symbol).map(str::to_string),
+                _ => None,
+            })
+            .collect();
+        let body = self.scope(names.iter().flatten().cloned().collect(), &outer, cx);
+
+        let mut inits = match kind.as_str() {
+            "letrec" | "letrec*" => body.clone(),
+            _ => renames.clone(),
+        };
+        let mut renamed = vec![];
+        for (spec, name) in specs.into_iter().zip(names) {
+            let (parts, tail) = split_list(spec);
+            let mut parts = parts.into_iter();
+            let mut spec = vec![];
+            if let Some(var) = parts.next() {
+                spec.push(self.rename(var, &body, cx));
+            }
+            spec.extend(self.rename_all(parts.collect(), &inits, cx));
+            renamed.push(join_list(spec, tail));
+
+            if let (Some(name), "let*") = (name, kind.as_str()) {
+                if let Some(fresh) = body.get(&name) {
+                    inits.insert(name, fresh.clone());
+                }
+            }
+        }
+        res.push(join_list(renamed, specs_tail));
+        res.extend(self.rename_all(items.collect(), &body, cx));
+        res
+    }
+}
+
+fn push_formals(formals: &Expr, binders: &mut HashSet<String>) {
+    match formals {
+        Expr::List(l) => {
+            for f in l.iter() {
+                match f {
+                    // (name default) after #!optional
+                    Expr::List(opt) => push_formals(opt.front().unwrap_or(f), binders),
+                    _ => push_formals(f, binders),
+                }
+            }
+        }
+        _ => match symbol(formals) {
+            Some(s) if !s.starts_with("#!") => {
+                binders.insert(s.to_string());
+            }
+            _ => {}
+        },
+    }
+}
+
+/*
+ * Expansion ahead of evaluation: macro uses are replaced by their
+ * expansion once, when a top-level form is read or given to eval,
+ * rather than every time they are evaluated. Uses of a macro defined
+ * later, or inside forms not walked here, are expanded by the evaluator
+ * when it gets to them.
+ */
+
+/// Expands the macro uses in `expr`. Names in `bound` are variables in
+/// the scope of `expr`, which shadow any macro of the same name.
+pub fn expand_all(expr: Expr, bound: &HashSet<String>, env: &Env) -> Result<Expr, EvalError> {
+    let head = match &expr {
+        Expr::List(l) => match l.front().and_then(symbol) {
+            Some(s) if !bound.contains(s) => env.get(s).ok().map(|v| (s.to_string(), v)),
+            _ => None,
+        },
+        _ => return Ok(expr),
+    };
+    let keyword = match head {
+        Some((_, Expr::Macro(m))) => return expand_all(m.expand(expr, bound, env)?, bound, env),
+        Some((name, Expr::Atom(a))) if matches!(*a, Atom::Syntax(_)) => name,
+        _ => String::new(),
+    };
+
+    let (items, tail) = split_list(expr);
+    let items = match keyword.as_str() {
+        "" | "if" | "begin" | "set!" | "and" | "or" | "when" | "unless" | "cond" | "delay"
+        | "delay-force" | "stream-cons" | "parameterize" => expand_each(items, 0, bound, env)?,
+        "lambda" | "define" | "define-macro" => {
+            let mut scope = bound.clone();
+            let mut formals = items.get(1);
+            // A curried define has a layer of formals per lambda
+            while let Some(layer @ Expr::List(l)) = formals {
+                push_formals(layer, &mut scope);
+                formals = l.front();
+            }
+            if keyword == "lambda" {
+                formals
+                    .into_iter()
+                    .for_each(|f| push_formals(f, &mut scope));
+            }
+            expand_body(items, 2, &scope, env)?
+        }
+        "case-lambda" => {
+            let mut items = items.into_iter();
+            let mut res: Vec<Expr> = items.next().into_iter().collect();
+            for clause in items {
+                let (clause, tail) = split_list(clause);
+                let mut scope = bound.clone();
+                if let Some(formals) = clause.first() {
+                    push_formals(formals, &mut scope);
+                }
+                res.push(join_list(expand_body(clause, 1, &scope, env)?, tail));
+            }
+            res
+        }
+        "let" | "let*" | "letrec" | "letrec*" => expand_let(items, bound, env)?,
+        // The data of each clause is left alone
+        "case" => {
+            let mut items = items.into_iter();
+            let mut res: Vec<Expr> = items.by_ref().take(2).collect();
+            if let Some(key) = res.pop() {
+                res.push(expand_all(key, bound, env)?);
+            }
+            for clause in items {
+                let (clause, tail) = split_list(clause);
+                res.push(join_list(expand_each(clause, 1, bound, env)?, tail));
+            }
+            res
+        }
+        // Quoted data, templates and forms binding in ways not known here
+        _ => items,
+    };
+    Ok(join_list(items, tail))
+}
+
+// Expands the items from `skip` on
+fn expand_each(
+    items: Vec<Expr>,
+    skip: usize,
+    bound: &HashSet<String>,
+    env: &Env,
+) -> Result<Vec<Expr>, EvalError> {
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(i, e)| match i < skip {
+            true => Ok(e),
+            false => expand_all(e, bound, env),
+        })
+        .collect()
+}
+
+// Expands a body from `skip` on, whose internal definitions shadow
+// macros as well
+fn expand_body(
+    items: Vec<Expr>,
+    skip: usize,
+    bound: &HashSet<String>,
+    env: &Env,
+) -> Result<Vec<Expr>, EvalError> {
+    let mut scope = bound.clone();
+    for form in items.iter().skip(skip) {
+        let defined = match form {
+            Expr::List(l) if l.len() > 2 => match l.front().and_then(symbol) {
+                Some(k) if k.starts_with("define") => l.iter().nth(1),
+                _ => None,
+            },
+            _ => None,
+        };
+        // The name being defined, curried or not
+        let mut target = defined;
+        while let Some(Expr::List(l)) = target {
+            target = l.front();
+        }
+        match (target, defined) {
+            (Some(name), _) if symbol(name).is_some() => push_formals(name, &mut scope),
+            // (define-values (a b . c) ...)
+            (_, Some(formals)) => push_formals(formals, &mut scope),
+            _ => {}
+        }
+    }
+    expand_each(items, skip, &scope, env)
+}
+
+// The initial values are expanded as if every variable of the let was
+// already bound, which may leave uses of a shadowed macro to the evaluator
+// but never expands a variable as a macro
+fn expand_let(
+    items: Vec<Expr>,
+    bound: &HashSet<String>,
+    env: &Env,
+) -> Result<Vec<Expr>, EvalError> {
+    let mut scope = bound.clone();
+    let mut skip = 1;
+    // A named let, its name may have been renamed by a template
+    let named = |e: &&Expr| matches!(e, Expr::Atom(a) if matches!(**a, Atom::Symbol(_) | Atom::Uninterned(_)));
+    if let Some(name) = items.get(1).filter(named) {
+        push_formals(name, &mut scope);
+        skip = 2;
+    }
+    let mut items = items.into_iter();
+    let mut res: Vec<Expr> = items.by_ref().take(skip).collect();
+
+    let (specs, specs_tail) = match items.next() {
+        Some(specs) => split_list(specs),
+        None => return Ok(res),
+    };
+    for spec in &specs {
+        if let Expr::List(b) = spec {
+            if let Some(name) = b.front() {
+                push_formals(name, &mut scope);
+            }
+        }
+    }
+    let mut expanded = vec![];
+    for spec in specs {
+        let (spec, tail) = split_list(spec);
+        expanded.push(join_list(expand_each(spec, 1, &scope, env)?, tail));
+    }
+    res.push(join_list(expanded, specs_tail));
+
+    let body: Vec<Expr> = items.collect();
+    res.extend(expand_body(body, 0, &scope, env)?);
+    Ok(res)
+}
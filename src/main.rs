@@ -1,21 +1,60 @@
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use std::collections::HashMap;
+use std::fs;
 use yali::env::*;
-use yali::evaluator::eval_expr;
+use yali::evaluator::eval_form;
 use yali::parser::error::ParserErr;
 use yali::parser::*;
+use yali::tokenizer::error::TokenizeErr;
 use yali::tokenizer::*;
 use yali::utils::init_map;
 use yali::*;
 
-fn repl() -> i32 {
-    let mut rl = Editor::<()>::new();
-
+fn global_env() -> Env {
     let mut initial_env = HashMap::<String, Expr>::default();
     init_map(&mut initial_env);
 
-    let env = Env::from(initial_env);
+    Env::from(initial_env)
+}
+
+fn read(source: &str) -> Result<Vec<Spanned>, Error> {
+    let mut tokens = tokenize(source)?;
+    Ok(parse_program(&mut tokens)?)
+}
+
+fn run_file(path: &str) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    };
+
+    let forms = match read(&source) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("{}: {}", path, e.report(&source));
+            return 1;
+        }
+    };
+
+    // Evaluate form by form, stopping at the first error
+    let env = global_env();
+    for form in forms {
+        if let Err(e) = eval_form(form.expr, &env) {
+            eprintln!("{}: {}", path, e.report(&source, &form.node));
+            return 1;
+        }
+    }
+    0
+}
+
+fn repl() -> i32 {
+    let mut rl = Editor::<()>::new();
+
+    let env = global_env();
     let exit_code;
 
     let mut lines = String::new();
@@ -26,23 +65,25 @@ fn repl() -> i32 {
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                lines.push(' ');
+                if !lines.is_empty() {
+                    lines.push('\n');
+                }
                 lines.push_str(&line);
 
-                let mut tokens = tokenize(lines.as_str());
-                let exprs = match parse(&mut tokens) {
+                let forms = match read(&lines) {
                     Ok(e) => {
-                        lines.clear();
                         prompt = "> ";
                         e
                     }
                     Err(e) => {
                         match e {
-                            ParserErr::UnclosedList => {
+                            // Wait for the rest of the expression
+                            Error::Parse(ParserErr::UnclosedList(_))
+                            | Error::Tokenize(TokenizeErr::UnterminatedString(_)) => {
                                 prompt = "| ";
                             }
                             _ => {
-                                println!("Parse error: {}", e);
+                                println!("{}", e.report(&lines));
                                 lines.clear();
                                 prompt = "> ";
                             }
@@ -50,14 +91,21 @@ fn repl() -> i32 {
                         continue;
                     }
                 };
-                let ev = match eval_expr(exprs, &env) {
-                    Ok(a) => a,
-                    a => {
-                        println!("{:?}", a);
-                        atom_nil!()
+                let source = std::mem::take(&mut lines);
+                for form in forms {
+                    let ev = match eval_form(form.expr, &env) {
+                        Ok(a) => a,
+                        Err(e) => {
+                            println!("{}", e.report(&source, &form.node));
+                            atom_nil!()
+                        }
+                    };
+                    match ev {
+                        // One line per value, none at all for (values)
+                        Expr::Values(vals) => vals.iter().for_each(|v| println!("{}", v)),
+                        ev => println!("{}", ev),
                     }
-                };
-                println!("{}", ev);
+                }
             }
             Err(ReadlineError::Interrupted) => {
                 println!("Interrupt");
@@ -77,6 +125,9 @@ fn repl() -> i32 {
 }
 
 fn main() {
-    let res = repl();
+    let res = match std::env::args().nth(1) {
+        Some(path) => run_file(&path),
+        None => repl(),
+    };
     std::process::exit(res)
 }
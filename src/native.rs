@@ -1,11 +1,27 @@
+use super::display::Displayed;
 use super::env::Env;
 use super::evaluator::error::EvalError;
-use super::evaluator::eval_expr;
-use super::parser::{Atom, Expr, Lambda, List};
+use super::evaluator::{self, eval_expr, eval_quasiquote, Tail};
+use super::exceptions::{self, Handler};
+use super::macros::{self, Macro, SyntaxRules};
+use super::number::Number;
+use super::parser::{Atom, Condition, Continuation, Expr, Lambda, List, Parameter};
+use super::port::{self, Port};
+use super::promise::{self, PromiseState};
+use crate::atom_bool;
 use crate::atom_nil;
 use crate::atom_num;
-use std::collections::LinkedList;
-use crate::utils::expr_is_nil;
+use crate::atom_str;
+use crate::expr_atom;
+use crate::utils::{
+    expr_is_nil, is_equal, is_eqv, is_procedure, list_from, list_to_args, unshare, values_from,
+    values_to_args,
+};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::rc::Rc;
 
 macro_rules! pop_back {
     ($list:ident) => {
@@ -19,388 +35,2070 @@ macro_rules! pop_front {
     };
 }
 
-// Expects only list args
-pub fn add(list: List, env: &Env) -> Result<Expr, EvalError> {
-    let mut sum: i32 = 0;
-    for exp in list {
-        let res = match eval_expr(exp, env)? {
-            Expr::Atom(a) => a,
-            _ => unimplemented!(),
-        };
-        let x = match *res {
-            Atom::Num(x) => x,
-            // This has to be the last element on the list
-            Atom::Nil => break,
-            a => return Err(EvalError::TypeMismatch("number, nil".to_string(), a)),
-        };
+fn check_args(list: &List, n: usize) -> Result<(), EvalError> {
+    if list.len() != n {
+        return Err(EvalError::WrongNumOfArgs(n, list.len()));
+    }
+    Ok(())
+}
 
-        sum += x;
+fn as_num(expr: Expr) -> Result<Number, EvalError> {
+    match expr {
+        Expr::Atom(a) => match *a {
+            Atom::Num(x) => Ok(x),
+            a => Err(EvalError::TypeMismatch("number".to_string(), a)),
+        },
+        e => Err(EvalError::ExprTypeMismatch("number".to_string(), e)),
     }
-    Ok(atom_num!(sum))
 }
 
-pub fn sub(mut list: List, env: &Env) -> Result<Expr, EvalError> {
-    // TODO: Check Nil termination
-    let car = list.pop_front().ok_or(EvalError::EmptyList)?;
-    let res = match eval_expr(car, env)? {
-        Expr::Atom(a) => a,
-        _ => unimplemented!(),
-    };
-    let mut res = match *res {
-        Atom::Num(x) => x,
-        a => return Err(EvalError::TypeMismatch("number".to_string(), a)),
-    };
+pub fn add(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let mut sum = Number::from(0);
+    for exp in list {
+        sum = sum + as_num(exp)?;
+    }
+    Ok(atom_num!(sum))
+}
 
-    let next = list.pop_front().ok_or(EvalError::EmptyList)?;
-    let next = match eval_expr(next, env)? {
-        Expr::Atom(a) => a,
-        _ => unimplemented!(),
-    };
-    match *next {
-        Atom::Num(x) => res -= x,
-        Atom::Nil => return Ok(Expr::Atom(Box::new(Atom::Num(-res)))),
-        a => return Err(EvalError::TypeMismatch("number, nil".to_string(), a)),
+pub fn sub(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let mut res = as_num(pop_front!(list)?)?;
+    if list.is_empty() {
+        return Ok(atom_num!(-res));
     }
 
     for exp in list {
-        let expr_res = eval_expr(exp, env)?;
-        let atom_res = match expr_res {
-            Expr::Atom(a) => a,
-            _ => unimplemented!(),
-        };
-        let x = match *atom_res {
-            Atom::Num(x) => x,
-            Atom::Nil => break,
-            a => return Err(EvalError::TypeMismatch("number, nil".to_string(), a)),
-        };
-        res -= x;
+        res = res - as_num(exp)?;
     }
 
     Ok(atom_num!(res))
 }
 
-pub fn mul(list: List, env: &Env) -> Result<Expr, EvalError> {
-    let mut res: i32 = 1;
+pub fn mul(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let mut res = Number::from(1);
     for exp in list {
-        let expr_res = eval_expr(exp, env)?;
-        let atom_res = match expr_res {
-            Expr::Atom(a) => a,
-            _ => unimplemented!(),
-        };
-        let x = match *atom_res {
-            Atom::Num(x) => x,
-            Atom::Nil => break,
-            a => return Err(EvalError::TypeMismatch("number, nil".to_string(), a)),
-        };
-        res *= x;
+        res = res * as_num(exp)?;
     }
 
     Ok(atom_num!(res))
 }
 
-pub fn div(mut list: List, env: &Env) -> Result<Expr, EvalError> {
-    // TODO: Check Nil termination
-    let car = list.pop_front().ok_or(EvalError::EmptyList)?;
-    let res = match eval_expr(car, env)? {
-        Expr::Atom(a) => a,
-        _ => unimplemented!(),
-    };
-    let mut res = match *res {
-        Atom::Num(x) => x,
-        a => return Err(EvalError::TypeMismatch("number".to_string(), a)),
-    };
-
-    let next = list.pop_front().ok_or(EvalError::EmptyList)?;
-    let atom = match eval_expr(next, env)? {
-        Expr::Atom(a) => *a,
-        _ => unimplemented!(),
-    };
-    match atom {
-        Atom::Num(x) => res /= x,
-        Atom::Nil => return Ok(atom_num!(1 / res)),
-        a => return Err(EvalError::TypeMismatch("number, nil".to_string(), a)),
+pub fn div(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let mut res = as_num(pop_front!(list)?)?;
+    if list.is_empty() {
+        let inv = Number::from(1).checked_div(&res);
+        return Ok(atom_num!(inv.ok_or(EvalError::DivisionByZero)?));
     }
 
     for exp in list {
-        let atom_res = match eval_expr(exp, env)? {
-            Expr::Atom(a) => *a,
-            _ => unimplemented!(),
-        };
-        let x = match atom_res {
-            Atom::Num(x) => x,
-            Atom::Nil => break,
-            a => return Err(EvalError::TypeMismatch("number, nil".to_string(), a)),
-        };
-        res /= x;
+        res = res
+            .checked_div(&as_num(exp)?)
+            .ok_or(EvalError::DivisionByZero)?;
     }
 
     Ok(atom_num!(res))
 }
 
-// TODO: handle all possible parameter variants
-pub fn define(mut list: List, env: &Env) -> Result<Expr, EvalError> {
-    let car = list.pop_front().ok_or(EvalError::EmptyList)?;
-    if !list.is_empty() && list.len() > 2 {
-        println!("{:?}", list);
-        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+// Numbers have to be monotonically ordered as given by `cmp`.
+// Every operand is checked even when the result is already known.
+fn compare(list: List, cmp: fn(Ordering) -> bool) -> Result<Expr, EvalError> {
+    let nums = list
+        .into_iter()
+        .map(as_num)
+        .collect::<Result<Vec<_>, _>>()?;
+    if nums.is_empty() {
+        return Err(EvalError::WrongNumOfArgs(1, 0));
     }
 
-    let sym = match car {
-        Expr::Atom(a) => *a,
-        _ => todo!("return better error"),
-    };
-    let sym = match sym {
-        Atom::Symbol(str) => str,
-        _ => return Err(EvalError::TypeMismatch("symbol".to_string(), sym)),
-    };
+    let res = nums
+        .windows(2)
+        .all(|w| w[0].num_cmp(&w[1]).is_some_and(cmp));
+    Ok(atom_bool!(res))
+}
 
-    let cdr = list.pop_front().unwrap();
-    Ok(match eval_expr(cdr, env)? {
-        Expr::Atom(a) => {
-            env.insert(sym.as_str(), Expr::Atom(a.clone()));
-            Expr::Atom(a)
-        }
-        Expr::Lambda(l) => {
-            env.insert(sym.as_str(), Expr::Lambda(l.clone()));
-            Expr::Lambda(l)
-        }
-        Expr::List(l) => {
-            env.insert(sym.as_str(), Expr::List(l.clone()));
-            Expr::List(l)
-        }
-        Expr::Quote(q) => {
-            env.insert(sym.as_str(), *q.clone());
-            *q
+pub fn num_eq(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare(list, |o| o == Ordering::Equal)
+}
+
+pub fn num_lt(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare(list, |o| o == Ordering::Less)
+}
+
+pub fn num_gt(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare(list, |o| o == Ordering::Greater)
+}
+
+pub fn num_le(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare(list, |o| o != Ordering::Greater)
+}
+
+pub fn num_ge(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare(list, |o| o != Ordering::Less)
+}
+
+pub fn eqv(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let (a, b) = (pop_front!(list)?, pop_front!(list)?);
+    Ok(atom_bool!(is_eqv(&a, &b)))
+}
+
+pub fn equal(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let (a, b) = (pop_front!(list)?, pop_front!(list)?);
+    Ok(atom_bool!(is_equal(&a, &b)))
+}
+
+pub fn not(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(!as_bool(&pop_front!(list)?)))
+}
+
+// (define name expr) binds the value of expr, while
+// (define (name . formals) body...) binds a procedure called name.
+// Curried definitions like (define ((adder n) x) (+ n x)) nest lambdas.
+// Inside a body, definitions land in the frame of that call.
+pub fn define(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    let mut target = pop_front!(list)?;
+
+    // Peel the curried layers off, wrapping the body in a lambda each time
+    let mut body = list;
+    while let Expr::List(l) = target {
+        let mut l = unshare(l);
+        let head = pop_front!(l)?;
+        let formals = match l.len() {
+            // either nil or the symbol of a rest argument
+            1 => pop_front!(l)?,
+            _ => Expr::List(Rc::new(l)),
+        };
+        if let Expr::List(_) = head {
+            let mut lambda = List::from([expr_atom!(Atom::Symbol("lambda".to_string())), formals]);
+            lambda.append(&mut body);
+            lambda.push_back(atom_nil!());
+            body = List::from([Expr::List(Rc::new(lambda))]);
+            target = head;
+        } else {
+            let name = as_symbol(head)?;
+            let lambda = make_lambda(formals, body, env, Some(name.clone()))?;
+            let val = Expr::Lambda(Rc::new(lambda));
+            env.insert(&name, val.clone());
+            return Ok(Tail::Return(val));
         }
-        _ => todo!(),
-    })
+    }
+
+    check_args(&body, 1)?;
+    let sym = as_symbol(target)?;
+    let val = eval_expr(pop_front!(body)?, env)?;
+    env.insert(sym.as_str(), val.clone());
+    Ok(Tail::Return(val))
 }
 
 /* Set global variables */
-// TODO: handle all possible parameter variants
-pub fn set(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+pub fn set(mut list: List, env: &Env) -> Result<Tail, EvalError> {
     let sym = pop_front!(list)?;
 
     if list.len() != 2 {
         return Err(EvalError::WrongNumOfArgs(2, list.len()));
     }
 
-    // TODO: Shrink this
-    let sym = match sym {
-        Expr::Atom(a) => *a,
-        _ => todo!("proper error report"),
-    };
-    let sym = match sym {
-        Atom::Symbol(str) => str,
-        a => return Err(EvalError::TypeMismatch("symbol".to_string(), a)),
-    };
-    ////
-
+    let sym = as_symbol(sym)?;
     if !env.contains_symbol(&sym) {
         return Err(EvalError::UndefinedSymbol(sym));
     }
 
-    let expr = list.pop_front().unwrap();
-    Ok(match eval_expr(expr, env)? {
-        Expr::Atom(a) => {
-            env.insert(sym.as_str(), Expr::Atom(a.clone()));
-            Expr::Atom(a)
-        }
-        Expr::Lambda(l) => {
-            env.insert(sym.as_str(), Expr::Lambda(l.clone()));
-            Expr::Lambda(l)
-        }
-        Expr::List(l) => {
-            env.insert(sym.as_str(), Expr::List(l.clone()));
-            Expr::List(l)
-        }
-        Expr::Quote(q) => {
-            env.insert(sym.as_str(), *q.clone());
-            *q
-        }
-        _ => todo!("more types"),
-    })
+    let expr = pop_front!(list)?;
+    let val = eval_expr(expr, env)?;
+    env.set(sym.as_str(), val.clone());
+    Ok(Tail::Return(val))
 }
 
-pub fn quote(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+pub fn quote(mut list: List, _env: &Env) -> Result<Tail, EvalError> {
     pop_and_check_nil(&mut list)?;
-    if list.len() != 1 {
-        Err(EvalError::WrongNumOfArgs(1, list.len()))
-    } else {
-        Ok(list.pop_front().unwrap())
-    }
+    check_args(&list, 1)?;
+    Ok(Tail::Return(pop_front!(list)?))
 }
 
-pub fn cons(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+pub fn quasiquote(mut list: List, env: &Env) -> Result<Tail, EvalError> {
     pop_and_check_nil(&mut list)?;
+    check_args(&list, 1)?;
+    Ok(Tail::Return(eval_quasiquote(pop_front!(list)?, 1, env)?))
+}
 
-    if list.len() != 2 {
-        Err(EvalError::WrongNumOfArgs(2, list.len()))
+pub fn cons(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let car = pop_front!(list)?;
+    let cdr = pop_front!(list)?;
+    let cons = if let Expr::List(l) = cdr {
+        let mut l = unshare(l);
+        l.push_front(car);
+        l
     } else {
-        let car = eval_expr(list.pop_front().unwrap(), env)?;
-        let cdr = eval_expr(list.pop_back().unwrap(), env)?;
-        let cons = if let Expr::List(mut l) = cdr {
-            l.push_front(car);
-            l
-        } else {
-            let mut l = List::new();
-            l.push_front(car);
-            l.push_back(cdr);
-            Box::new(l)
-        };
-        Ok(Expr::List(cons))
-    }
+        let mut l = List::new();
+        l.push_front(car);
+        l.push_back(cdr);
+        l
+    };
+    Ok(Expr::List(Rc::new(cons)))
 }
 
-// TODO: Handle all possible parameter variants
-pub fn lambda(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+pub fn list(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(list_from(list))
+}
+
+pub fn lambda(mut list: List, env: &Env) -> Result<Tail, EvalError> {
     pop_and_check_nil(&mut list)?;
     if list.len() < 2 {
         return Err(EvalError::WrongNumOfArgs(2, list.len()));
     }
 
-    let lambda_env = Env::new(None);
-    // TODO: This has to be a Rc or reference,
-    // should not be a copy
-    lambda_env.set_outer(env.clone());
+    let formals = pop_front!(list)?;
+    let lambda = make_lambda(formals, list, env, None)?;
+    Ok(Tail::Return(Expr::Lambda(Rc::new(lambda))))
+}
+
+// Formals are required symbols, then optional ones after `#!optional',
+// written either as `name' or `(name default)', and finally a rest
+// argument, given after `#!rest' or as the tail of a dotted list.
+// A lone symbol, as in (lambda args ...), takes every argument.
+fn make_lambda(
+    formals: Expr,
+    body: List,
+    env: &Env,
+    name: Option<String>,
+) -> Result<Lambda, EvalError> {
+    if body.is_empty() {
+        return Err(EvalError::BadSyntax("body cannot be empty".to_string()));
+    }
 
-    let formals = list.pop_front().unwrap();
     let mut args_list: Vec<String> = vec![];
-    match formals {
-        Expr::List(arglist) => {
-            let mut atom_list = match as_atoms(*arglist) {
-                Ok(l) => l,
-                _ => unimplemented!(),
-            };
-            let last = atom_list.pop_back().unwrap();
-            for elem in atom_list {
-                match elem {
-                    Atom::Symbol(s) => {
-                        lambda_env.insert(s.as_str(), Expr::Atom(Box::new(Atom::Nil)));
-                        args_list.push(s);
+    let mut optionals = vec![];
+    let mut rest = None;
+    let mut formals = match formals {
+        Expr::List(l) => unshare(l),
+        e => List::from([e]),
+    };
+    match pop_back!(formals)? {
+        nil if expr_is_nil(&nil) => {}
+        tail => rest = Some(as_symbol(tail)?),
+    }
+
+    let bad_formals = || EvalError::BadSyntax("malformed lambda formals".to_string());
+    let mut marker = "";
+    for formal in formals {
+        match formal {
+            Expr::Atom(a) => match *a {
+                Atom::Symbol(s) if s == "#!optional" || s == "#!rest" => {
+                    if (marker, s.as_str()) == ("#!rest", "#!optional") || marker == s {
+                        return Err(bad_formals());
                     }
-                    _ => unimplemented!(),
+                    marker = if s == "#!rest" {
+                        "#!rest"
+                    } else {
+                        "#!optional"
+                    };
                 }
-            }
-            match last {
-                // Mixed argument list
-                Atom::Symbol(s) => {
-                    lambda_env.insert(s.as_str(), Expr::List(Box::new(List::new())));
-                    args_list.push(s);
+                a => {
+                    let s = as_symbol(expr_atom!(a))?;
+                    match marker {
+                        "" => args_list.push(s),
+                        "#!optional" => optionals.push((s, atom_nil!())),
+                        _ if rest.is_none() => rest = Some(s),
+                        _ => return Err(bad_formals()),
+                    }
                 }
-                // Fixed argument list
-                Atom::Nil => {}
-                _ => return Err(EvalError::TypeMismatch("symbol, nil".to_string(), last)),
-            }
-        }
-        // Variadic argument list
-        Expr::Atom(atom) => {
-            if let Atom::Symbol(s) = *atom {
-                lambda_env.insert(s.as_str(), Expr::Atom(Box::new(Atom::Nil)));
-                args_list.push(s);
+            },
+            Expr::List(l) if marker == "#!optional" => {
+                let mut l = list_to_args(Expr::List(l))?;
+                check_args(&l, 2)?;
+                let name = as_symbol(pop_front!(l)?)?;
+                optionals.push((name, pop_front!(l)?));
             }
+            e => return Err(EvalError::ExprTypeMismatch("symbol".to_string(), e)),
         }
-        // TODO: Throw better error
-        _ => return Err(EvalError::TypeMismatch("list, atom".to_string(), Atom::Nil)),
+    }
+    if marker == "#!rest" && rest.is_none() {
+        return Err(bad_formals());
     }
 
-    let lambda = Lambda {
+    Ok(Lambda {
+        name,
         args_list,
-        body: list,
-        env: lambda_env,
-    };
-
-    Ok(Expr::Lambda(Box::new(lambda)))
+        optionals,
+        rest,
+        body,
+        // Closures capture the environment they were created in
+        env: env.clone(),
+    })
 }
 
-pub fn car(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+// (case-lambda ((x) ...) ((x y . rest) ...)) calls the first clause
+// taking as many arguments as given
+pub fn case_lambda(mut list: List, env: &Env) -> Result<Tail, EvalError> {
     pop_and_check_nil(&mut list)?;
-    if list.len() != 1 {
-        return Err(EvalError::WrongNumOfArgs(1, list.len()));
-    }
-    match eval_expr(pop_front!(list)?, env)? {
-        Expr::List(mut l) => pop_front!(l),
+    let clauses = list
+        .into_iter()
+        .map(|clause| {
+            let mut clause = list_to_args(clause)?;
+            let formals = pop_front!(clause)?;
+            Ok(Rc::new(make_lambda(formals, clause, env, None)?))
+        })
+        .collect::<Result<Vec<_>, EvalError>>()?;
+    Ok(Tail::Return(Expr::CaseLambda(Rc::new(clauses))))
+}
+
+pub fn car(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    match pop_front!(list)? {
+        Expr::List(l) => l.front().cloned().ok_or(EvalError::EmptyList),
         e => Err(EvalError::ExprTypeMismatch("list".to_string(), e)),
     }
 }
 
-pub fn cdr(mut list: List, env: &Env) -> Result<Expr, EvalError> {
-    pop_and_check_nil(&mut list)?;
-    if list.len() != 1 {
-        return Err(EvalError::WrongNumOfArgs(1, list.len()));
-    }
-    match eval_expr(pop_front!(list)?, env)? {
-        Expr::List(mut l) => {
+pub fn cdr(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    match pop_front!(list)? {
+        Expr::List(l) => {
+            let mut l = unshare(l);
             pop_front!(l)?;
-            Ok(Expr::List(l))
-        },
+            // Only the tail is left, either nil or the cdr of a dotted pair
+            if l.len() == 1 {
+                pop_front!(l)
+            } else {
+                Ok(Expr::List(Rc::new(l)))
+            }
+        }
         e => Err(EvalError::ExprTypeMismatch("list".to_string(), e)),
     }
 }
 
-pub fn ifcond(mut list: List, env: &Env) -> Result<Expr, EvalError> {
-    pop_back!(list)?;
+pub fn procedure_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(is_procedure(&pop_front!(list)?)))
+}
+
+// (apply f a b '(c d)) calls f with a, b, c and d
+pub fn apply(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let f = pop_front!(list)?;
+    let mut args = list_to_args(pop_back!(list)?)?;
+    while let Some(arg) = list.pop_back() {
+        args.push_front(arg);
+    }
+    evaluator::apply(&f, args, env)
+}
+
+// Takes the n-th element of every list, `None` once any of them runs out
+fn next_args(lists: &mut [List]) -> Option<List> {
+    lists.iter_mut().map(|l| l.pop_front()).collect()
+}
+
+pub fn mapcar(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let f = pop_front!(list)?;
+    let mut lists = list
+        .into_iter()
+        .map(list_to_args)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut res = List::new();
+    while let Some(args) = next_args(&mut lists) {
+        res.push_back(evaluator::apply(&f, args, env)?);
+    }
+    Ok(list_from(res))
+}
+
+pub fn for_each(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let f = pop_front!(list)?;
+    let mut lists = list
+        .into_iter()
+        .map(list_to_args)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    while let Some(args) = next_args(&mut lists) {
+        evaluator::apply(&f, args, env)?;
+    }
+    Ok(atom_nil!())
+}
+
+pub fn ifcond(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
     if list.len() < 2 || list.len() > 3 {
-        return Err(EvalError::WrongNumOfArgs(3, list.len() - 1));
+        return Err(EvalError::WrongNumOfArgs(3, list.len()));
     }
     let test = list.pop_front().unwrap();
     let test = eval_expr(test, env)?;
     if as_bool(&test) {
-        eval_expr(list.pop_front().unwrap(), env)
+        Ok(Tail::Eval(list.pop_front().unwrap(), env.clone()))
     } else if list.len() == 2 {
-        eval_expr(list.pop_back().unwrap(), env)
+        Ok(Tail::Eval(list.pop_back().unwrap(), env.clone()))
     } else {
-        Ok(atom_nil!())
+        Ok(Tail::Return(atom_nil!()))
     }
 }
 
-/*
-fn pop_back(list: &mut List) -> Result<Expr, EvalError> {
-    list.pop_back().ok_or(EvalError::EmptyList)
+// Reads the `((name init) ...)' part of the let family
+fn let_bindings(bindings: Expr) -> Result<Vec<(String, Expr)>, EvalError> {
+    list_to_args(bindings)?
+        .into_iter()
+        .map(|binding| {
+            let mut binding = list_to_args(binding)?;
+            check_args(&binding, 2)?;
+            let name = as_symbol(pop_front!(binding)?)?;
+            Ok((name, pop_front!(binding)?))
+        })
+        .collect()
 }
 
-fn pop_front(list: &mut List) -> Result<Expr, EvalError> {
-    list.pop_front().ok_or(EvalError::EmptyList)
-}
-*/
+// (let ((x 1) (y 2)) body) binds every init, evaluated in the outer
+// environment, in a single new frame.
+// (let loop ((i 0)) body) also binds `loop' to a procedure running the
+// body, which loops without growing the stack when called in tail position.
+pub fn let_form(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
 
-fn pop_and_check_nil(list: &mut List) -> Result<Expr, EvalError> {
-    match list.pop_back() {
-        Some(expr) =>
-            match expr_is_nil(&expr) {
-                true => Ok(expr),
-                false => Err(EvalError::DottedList),
+    let name = match list.front() {
+        Some(Expr::Atom(a)) if matches!(**a, Atom::Symbol(_) | Atom::Uninterned(_)) => {
+            Some(as_symbol(pop_front!(list)?)?)
+        }
+        _ => None,
+    };
+    let bindings = let_bindings(pop_front!(list)?)?;
+    let mut args = List::new();
+    for (_, init) in &bindings {
+        args.push_back(eval_expr(init.clone(), env)?);
+    }
+
+    let frame = Env::new(Some(env.clone()));
+    match name {
+        Some(name) => {
+            if list.is_empty() {
+                return Err(EvalError::BadSyntax("body cannot be empty".to_string()));
             }
-        None => Err(EvalError::EmptyList),
+            let lambda = Rc::new(Lambda {
+                name: Some(name.clone()),
+                args_list: bindings.into_iter().map(|(name, _)| name).collect(),
+                optionals: vec![],
+                rest: None,
+                body: list,
+                env: frame.clone(),
+            });
+            frame.insert(&name, Expr::Lambda(lambda.clone()));
+            evaluator::eval_lambda(&lambda, args)
+        }
+        None => {
+            for ((name, _), val) in bindings.iter().zip(args) {
+                frame.insert(name, val);
+            }
+            evaluator::eval_body(list, &frame)
+        }
     }
 }
 
-fn as_bool(expr: &Expr) -> bool {
-    if let Expr::Atom(a) = expr {
-        match **a {
-            Atom::Bool(b) => b,
-            _ => true,
+// Each binding of let* gets its own frame, so it sees the previous ones
+pub fn let_star(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+
+    let mut frame = Env::new(Some(env.clone()));
+    for (name, init) in let_bindings(pop_front!(list)?)? {
+        let val = eval_expr(init, &frame)?;
+        frame = Env::new(Some(frame));
+        frame.insert(&name, val);
+    }
+    evaluator::eval_body(list, &frame)
+}
+
+// The inits of letrec are evaluated in the new frame, so they can refer
+// to each other. letrec binds them once all are evaluated, letrec* binds
+// each one as soon as it is evaluated.
+fn letrec_common(mut list: List, env: &Env, sequential: bool) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+
+    let frame = Env::new(Some(env.clone()));
+    let mut vals = vec![];
+    for (name, init) in let_bindings(pop_front!(list)?)? {
+        let val = eval_expr(init, &frame)?;
+        if sequential {
+            frame.insert(&name, val);
+        } else {
+            vals.push((name, val));
+        }
+    }
+    for (name, val) in vals {
+        frame.insert(&name, val);
+    }
+    evaluator::eval_body(list, &frame)
+}
+
+pub fn letrec(list: List, env: &Env) -> Result<Tail, EvalError> {
+    letrec_common(list, env, false)
+}
+
+pub fn letrec_star(list: List, env: &Env) -> Result<Tail, EvalError> {
+    letrec_common(list, env, true)
+}
+
+pub fn begin(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    evaluator::eval_body(list, env)
+}
+
+// Returns the first false value or the last one, which is in tail position
+pub fn and(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    let last = match list.pop_back() {
+        Some(last) => last,
+        None => return Ok(Tail::Return(atom_bool!(true))),
+    };
+    for expr in list {
+        let val = eval_expr(expr, env)?;
+        if !as_bool(&val) {
+            return Ok(Tail::Return(val));
+        }
+    }
+    Ok(Tail::Eval(last, env.clone()))
+}
+
+// Returns the first true value or the last one, which is in tail position
+pub fn or(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    let last = match list.pop_back() {
+        Some(last) => last,
+        None => return Ok(Tail::Return(atom_bool!(false))),
+    };
+    for expr in list {
+        let val = eval_expr(expr, env)?;
+        if as_bool(&val) {
+            return Ok(Tail::Return(val));
         }
+    }
+    Ok(Tail::Eval(last, env.clone()))
+}
+
+fn when_common(mut list: List, env: &Env, expected: bool) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let test = eval_expr(pop_front!(list)?, env)?;
+    if as_bool(&test) == expected {
+        evaluator::eval_body(list, env)
     } else {
-        true
+        Ok(Tail::Return(atom_nil!()))
     }
 }
 
-fn as_atoms(list: List) -> Result<LinkedList<Atom>, usize> {
-    let iter = list.iter().map(|expr| {
-        if let Expr::Atom(a) = expr {
-            (true, *a.clone())
-        } else {
-            (false, Atom::Nil)
+pub fn when(list: List, env: &Env) -> Result<Tail, EvalError> {
+    when_common(list, env, true)
+}
+
+pub fn unless(list: List, env: &Env) -> Result<Tail, EvalError> {
+    when_common(list, env, false)
+}
+
+fn is_keyword(expr: &Expr, keyword: &str) -> bool {
+    match expr {
+        Expr::Atom(a) => matches!(&**a, Atom::Symbol(s) if s == keyword),
+        _ => false,
+    }
+}
+
+// The body of a selected cond or case clause: either some expressions,
+// or `=> f' to call f with the value that selected the clause
+fn clause_body(mut body: List, val: Expr, env: &Env) -> Result<Tail, EvalError> {
+    if body.front().is_some_and(|e| is_keyword(e, "=>")) {
+        body.pop_front();
+        check_args(&body, 1)?;
+        let f = eval_expr(pop_front!(body)?, env)?;
+        return evaluator::apply_tail(&f, List::from([val]), env);
+    }
+    if body.is_empty() {
+        return Ok(Tail::Return(val));
+    }
+    evaluator::eval_body(body, env)
+}
+
+// (cond (test body...) (test => f) (else body...))
+pub fn cond(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    Ok(cond_clauses(list, env)?.unwrap_or(Tail::Return(atom_nil!())))
+}
+
+// Runs the first clause whose test is true, `None' if there is none
+fn cond_clauses(list: List, env: &Env) -> Result<Option<Tail>, EvalError> {
+    let clauses = list
+        .into_iter()
+        .map(list_to_args)
+        .collect::<Result<Vec<_>, _>>()?;
+    let count = clauses.len();
+
+    for (i, mut clause) in clauses.into_iter().enumerate() {
+        let test = clause
+            .pop_front()
+            .ok_or_else(|| EvalError::BadSyntax("cond clause cannot be empty".to_string()))?;
+        if is_keyword(&test, "else") {
+            if i + 1 != count {
+                return Err(EvalError::BadSyntax(
+                    "else must be the last cond clause".to_string(),
+                ));
+            }
+            return evaluator::eval_body(clause, env).map(Some);
+        }
+        let val = eval_expr(test, env)?;
+        if as_bool(&val) {
+            return clause_body(clause, val, env).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+// (case key ((datum...) body...) (else body...)), data are compared with eqv?
+pub fn case(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    let key = eval_expr(pop_front!(list)?, env)?;
+    let clauses = list
+        .into_iter()
+        .map(list_to_args)
+        .collect::<Result<Vec<_>, _>>()?;
+    let count = clauses.len();
+
+    for (i, mut clause) in clauses.into_iter().enumerate() {
+        let data = clause
+            .pop_front()
+            .ok_or_else(|| EvalError::BadSyntax("case clause cannot be empty".to_string()))?;
+        if clause.is_empty() {
+            return Err(EvalError::BadSyntax("case clause needs a body".to_string()));
+        }
+        if is_keyword(&data, "else") {
+            if i + 1 != count {
+                return Err(EvalError::BadSyntax(
+                    "else must be the last case clause".to_string(),
+                ));
+            }
+            return clause_body(clause, key, env);
         }
+        if list_to_args(data)?.iter().any(|d| is_eqv(d, &key)) {
+            return clause_body(clause, key, env);
+        }
+    }
+    Ok(Tail::Return(atom_nil!()))
+}
+
+// (syntax-rules (literal...) (pattern template)...), optionally with a
+// custom ellipsis before the literals
+pub fn syntax_rules(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    let ellipsis = match list.front() {
+        Some(Expr::Atom(a)) if matches!(**a, Atom::Symbol(_)) => as_symbol(pop_front!(list)?)?,
+        _ => "...".to_string(),
+    };
+    let literals = list_to_args(pop_front!(list)?)?
+        .into_iter()
+        .map(as_symbol)
+        .collect::<Result<Vec<_>, _>>()?;
+    let rules = list
+        .into_iter()
+        .map(|rule| {
+            let mut rule = list_to_args(rule)?;
+            check_args(&rule, 2)?;
+            let pattern = pop_front!(rule)?;
+            if !matches!(pattern, Expr::List(_)) {
+                return Err(EvalError::ExprTypeMismatch("list".to_string(), pattern));
+            }
+            Ok((pattern, pop_front!(rule)?))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let transformer = Macro::Rules(SyntaxRules {
+        ellipsis,
+        literals,
+        rules,
+        env: env.clone(),
     });
+    Ok(Tail::Return(Expr::Macro(Rc::new(transformer))))
+}
+
+fn as_macro(expr: Expr, env: &Env) -> Result<Expr, EvalError> {
+    match eval_expr(expr, env)? {
+        m @ Expr::Macro(_) => Ok(m),
+        e => Err(EvalError::ExprTypeMismatch("macro".to_string(), e)),
+    }
+}
+
+pub fn define_syntax(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    check_args(&list, 2)?;
+    let name = as_symbol(pop_front!(list)?)?;
+    let transformer = as_macro(pop_front!(list)?, env)?;
+    env.insert(&name, transformer.clone());
+    Ok(Tail::Return(transformer))
+}
+
+// The transformers of let-syntax are made in the outer environment,
+// the ones of letrec-syntax in the new frame with the others
+fn let_syntax_common(mut list: List, env: &Env, recursive: bool) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+
+    let frame = Env::new(Some(env.clone()));
+    for (name, spec) in let_bindings(pop_front!(list)?)? {
+        let transformer = as_macro(spec, if recursive { &frame } else { env })?;
+        frame.insert(&name, transformer);
+    }
+    evaluator::eval_body(list, &frame)
+}
+
+pub fn let_syntax(list: List, env: &Env) -> Result<Tail, EvalError> {
+    let_syntax_common(list, env, false)
+}
+
+pub fn letrec_syntax(list: List, env: &Env) -> Result<Tail, EvalError> {
+    let_syntax_common(list, env, true)
+}
+
+// (define-macro (name . formals) body...) defines a macro whose
+// transformer gets the operands of each use unevaluated, and returns the
+// form to evaluate in its place. (define-macro name transformer) works too.
+pub fn define_macro(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    let (name, transformer) = match pop_front!(list)? {
+        Expr::List(l) => {
+            let mut l = unshare(l);
+            let name = as_symbol(pop_front!(l)?)?;
+            let formals = match l.len() {
+                1 => pop_front!(l)?,
+                _ => Expr::List(Rc::new(l)),
+            };
+            let lambda = make_lambda(formals, list, env, Some(name.clone()))?;
+            (name, Expr::Lambda(Rc::new(lambda)))
+        }
+        target => {
+            check_args(&list, 1)?;
+            let transformer = eval_expr(pop_front!(list)?, env)?;
+            if !is_procedure(&transformer) {
+                return Err(EvalError::ExprTypeMismatch(
+                    "procedure".to_string(),
+                    transformer,
+                ));
+            }
+            (as_symbol(target)?, transformer)
+        }
+    };
+    let transformer = Expr::Macro(Rc::new(Macro::Procedural(transformer)));
+    env.insert(&name, transformer.clone());
+    Ok(Tail::Return(transformer))
+}
 
-    let (bools, atoms): (Vec<bool>, LinkedList<Atom>) = iter.unzip();
-    let error = bools.iter().position(|b| !b);
-    if let Some(err) = error {
-        return Err(err);
+// Calls f with an escape continuation, which makes call/cc return
+// right away with the value it is called with
+pub fn call_cc(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    let f = pop_front!(list)?;
+    let k = Rc::new(Continuation {
+        active: Cell::new(true),
+    });
+    let depth = evaluator::trace_len();
+    let res = evaluator::apply(&f, List::from([Expr::Continuation(k.clone())]), env);
+    k.active.set(false);
+    match res {
+        Err(EvalError::Escape(target, val)) if Rc::ptr_eq(&target, &k) => {
+            // An error escaped from, as by its handler, won't be reported
+            evaluator::truncate_trace(depth);
+            Ok(*val)
+        }
+        res => res,
+    }
+}
+
+// Calls before, thunk and after in order. after also runs when thunk is
+// left by an error or by escaping to a continuation.
+pub fn dynamic_wind(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 3)?;
+    let before = pop_front!(list)?;
+    let thunk = pop_front!(list)?;
+    let after = pop_front!(list)?;
+
+    evaluator::apply(&before, List::new(), env)?;
+    let res = evaluator::apply(&thunk, List::new(), env);
+    evaluator::apply(&after, List::new(), env)?;
+    res
+}
+
+// (error message irritant...) raises a new error object
+pub fn error(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    let message = pop_front!(list)?;
+    let condition = Expr::Condition(Rc::new(Condition {
+        message,
+        irritants: list,
+    }));
+    exceptions::raise(condition, false, env)
+}
+
+pub fn raise(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    exceptions::raise(pop_front!(list)?, false, env)
+}
+
+// Like raise, but the value of the handler is returned
+pub fn raise_continuable(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    exceptions::raise(pop_front!(list)?, true, env)
+}
+
+pub fn with_exception_handler(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let handler = pop_front!(list)?;
+    let thunk = pop_front!(list)?;
+    if !is_procedure(&handler) {
+        return Err(EvalError::ExprTypeMismatch(
+            "procedure".to_string(),
+            handler,
+        ));
+    }
+
+    // Errors are handed to the handler where they happen, those of calling
+    // the thunk itself are the last ones that can still reach it here
+    exceptions::with_handler(Handler::Procedure(handler), || {
+        evaluator::apply(&thunk, List::new(), env).map_err(|e| exceptions::raise_error(e, env))
+    })
+}
+
+// (guard (var clause...) body...) evaluates body, and if something is
+// raised binds it to var and picks a clause as cond does. When none
+// matches it is raised again.
+pub fn guard(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let mut spec = list_to_args(pop_front!(list)?)?;
+    let var = as_symbol(pop_front!(spec)?)?;
+
+    // The body is not in tail position, the guard has to stay around
+    let res = exceptions::with_handler(Handler::Guard, || {
+        let mut res = Ok(atom_nil!());
+        for expr in list {
+            res = eval_expr(expr, env);
+            if res.is_err() {
+                break;
+            }
+        }
+        res
+    });
+    let err = match res {
+        Ok(val) => return Ok(Tail::Return(val)),
+        Err(e) => e,
+    };
+    let obj = match exceptions::condition_of(&err) {
+        Some(obj) => obj,
+        None => return Err(err),
+    };
+
+    // The calls the error went through are only of use if it goes on
+    let trace = evaluator::take_trace();
+    let frame = Env::new(Some(env.clone()));
+    frame.insert(&var, obj.clone());
+    let clause = cond_clauses(spec, &frame)?;
+    if clause.is_none() {
+        evaluator::set_trace(trace);
+    }
+    match clause {
+        Some(tail) => Ok(tail),
+        // Built-in errors keep going as they are, so they are still
+        // reported with their own message at the top level
+        None if !matches!(err, EvalError::Raise(_)) => Err(err),
+        None => Ok(Tail::Return(exceptions::raise(obj, true, env)?)),
+    }
+}
+
+fn as_condition(expr: Expr) -> Result<Rc<Condition>, EvalError> {
+    match expr {
+        Expr::Condition(c) => Ok(c),
+        e => Err(EvalError::ExprTypeMismatch("error object".to_string(), e)),
+    }
+}
+
+pub fn error_object_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(matches!(pop_front!(list)?, Expr::Condition(_))))
+}
+
+pub fn error_object_message(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(as_condition(pop_front!(list)?)?.message.clone())
+}
+
+pub fn error_object_irritants(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(list_from(
+        as_condition(pop_front!(list)?)?.irritants.clone(),
+    ))
+}
+
+pub fn values(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(values_from(list))
+}
+
+// Calls consumer with the values returned by producer as its arguments
+pub fn call_with_values(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let producer = pop_front!(list)?;
+    let consumer = pop_front!(list)?;
+    let vals = evaluator::apply(&producer, List::new(), env)?;
+    evaluator::apply(&consumer, values_to_args(vals), env)
+}
+
+// Binds the values of `init' to `formals', which take the same shapes
+// as the formals of a lambda
+fn bind_values(formals: Expr, init: Expr, env: &Env, frame: &Env) -> Result<(), EvalError> {
+    let vals = values_to_args(eval_expr(init, env)?);
+    let binder = Rc::new(make_lambda(formals, List::from([atom_nil!()]), env, None)?);
+    evaluator::bind_args(&binder, vals, frame)
+}
+
+// (let-values (((a b) (values 1 2)) ((c . d) (values 3 4 5))) body...)
+pub fn let_values(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+
+    let frame = Env::new(Some(env.clone()));
+    for (formals, init) in values_bindings(pop_front!(list)?)? {
+        bind_values(formals, init, env, &frame)?;
+    }
+    evaluator::eval_body(list, &frame)
+}
+
+// Like let-values, but each init sees the bindings before it
+pub fn let_star_values(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+
+    let mut frame = Env::new(Some(env.clone()));
+    for (formals, init) in values_bindings(pop_front!(list)?)? {
+        let next = Env::new(Some(frame.clone()));
+        bind_values(formals, init, &frame, &next)?;
+        frame = next;
+    }
+    evaluator::eval_body(list, &frame)
+}
+
+fn values_bindings(bindings: Expr) -> Result<Vec<(Expr, Expr)>, EvalError> {
+    list_to_args(bindings)?
+        .into_iter()
+        .map(|binding| {
+            let mut binding = list_to_args(binding)?;
+            check_args(&binding, 2)?;
+            Ok((pop_front!(binding)?, pop_front!(binding)?))
+        })
+        .collect()
+}
+
+pub fn define_values(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    check_args(&list, 2)?;
+    let formals = pop_front!(list)?;
+    bind_values(formals, pop_front!(list)?, env, env)?;
+    Ok(Tail::Return(atom_nil!()))
+}
+
+// (receive formals expr body...) from SRFI 8
+pub fn receive(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    if list.len() < 3 {
+        return Err(EvalError::WrongNumOfArgs(3, list.len()));
+    }
+    let formals = pop_front!(list)?;
+    let vals = values_to_args(eval_expr(pop_front!(list)?, env)?);
+    let lambda = Rc::new(make_lambda(formals, list, env, None)?);
+    evaluator::eval_lambda(&lambda, vals)
+}
+
+pub fn delay(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    check_args(&list, 1)?;
+    let expr = pop_front!(list)?;
+    Ok(Tail::Return(promise::make(PromiseState::Delayed(
+        expr,
+        env.clone(),
+    ))))
+}
+
+// Like delay, but the expression gives a promise, and forcing them
+// one after the other doesn't grow the stack
+pub fn delay_force(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    check_args(&list, 1)?;
+    let expr = pop_front!(list)?;
+    Ok(Tail::Return(promise::make(PromiseState::Lazy(
+        expr,
+        env.clone(),
+    ))))
+}
+
+pub fn make_promise(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    match pop_front!(list)? {
+        p @ Expr::Promise(_) => Ok(p),
+        val => Ok(promise::make(PromiseState::Done(val))),
+    }
+}
+
+// Anything but a promise is its own value
+pub fn force(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    match pop_front!(list)? {
+        Expr::Promise(p) => promise::force(&p),
+        val => Ok(val),
+    }
+}
+
+pub fn promise_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(matches!(pop_front!(list)?, Expr::Promise(_))))
+}
+
+/*
+ * Streams are promises of either () or a pair of a promise of the first
+ * element and the stream with the rest.
+ */
+
+pub fn stream_null() -> Expr {
+    promise::make(PromiseState::Done(atom_nil!()))
+}
+
+fn stream_pair(car: Expr, cdr: Expr) -> Expr {
+    promise::make(PromiseState::Done(Expr::List(Rc::new(List::from([
+        car, cdr,
+    ])))))
+}
+
+// Forces a stream into either `None' for the empty stream or its pair
+fn force_stream(stream: Expr) -> Result<Option<(Expr, Expr)>, EvalError> {
+    let val = match &stream {
+        Expr::Promise(p) => promise::force(p)?,
+        _ => return Err(EvalError::ExprTypeMismatch("stream".to_string(), stream)),
+    };
+    match val {
+        nil if expr_is_nil(&nil) => Ok(None),
+        Expr::List(l) if l.len() == 2 => {
+            let mut l = unshare(l);
+            Ok(Some((pop_front!(l)?, pop_front!(l)?)))
+        }
+        _ => Err(EvalError::ExprTypeMismatch("stream".to_string(), stream)),
+    }
+}
+
+fn stream_pair_of(stream: Expr) -> Result<(Expr, Expr), EvalError> {
+    force_stream(stream.clone())?
+        .ok_or_else(|| EvalError::ExprTypeMismatch("stream pair".to_string(), stream))
+}
+
+// (stream-cons a b) delays both a and the stream b
+pub fn stream_cons(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    check_args(&list, 2)?;
+    let car = promise::make(PromiseState::Delayed(pop_front!(list)?, env.clone()));
+    let cdr = promise::make(PromiseState::Lazy(pop_front!(list)?, env.clone()));
+    Ok(Tail::Return(stream_pair(car, cdr)))
+}
+
+pub fn stream_null_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(force_stream(pop_front!(list)?)?.is_none()))
+}
+
+pub fn stream_pair_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(force_stream(pop_front!(list)?)?.is_some()))
+}
+
+pub fn stream_car(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    let (car, _) = stream_pair_of(pop_front!(list)?)?;
+    force(List::from([car]), env)
+}
+
+pub fn stream_cdr(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(stream_pair_of(pop_front!(list)?)?.1)
+}
+
+pub fn list_to_stream(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    let items = list_to_args(pop_front!(list)?)?;
+    Ok(items.into_iter().rev().fold(stream_null(), |cdr, item| {
+        stream_pair(promise::make(PromiseState::Done(item)), cdr)
+    }))
+}
+
+// (stream->list [n] stream) forces the first n elements, or all of them
+pub fn stream_to_list(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    let (mut count, mut stream) = match list.len() {
+        1 => (None, pop_front!(list)?),
+        2 => (Some(as_count(pop_front!(list)?)?), pop_front!(list)?),
+        n => return Err(EvalError::WrongNumOfArgs(2, n)),
+    };
+    let mut items = List::new();
+    while count != Some(0) {
+        match force_stream(stream)? {
+            Some((car, cdr)) => {
+                items.push_back(force(List::from([car]), env)?);
+                stream = cdr;
+                count = count.map(|n| n - 1);
+            }
+            None => break,
+        }
+    }
+    Ok(list_from(items))
+}
+
+fn as_count(expr: Expr) -> Result<usize, EvalError> {
+    match as_num(expr.clone())? {
+        Number::Fixnum(n) if n >= 0 => Ok(n as usize),
+        _ => Err(EvalError::ExprTypeMismatch(
+            "non-negative integer".to_string(),
+            expr,
+        )),
+    }
+}
+
+// (stream-map f stream...) is lazy, the elements are computed as the
+// resulting stream is forced
+pub fn stream_map(list: List, env: &Env) -> Result<Expr, EvalError> {
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    Ok(promise::lazy_call(
+        stream_map_step,
+        list.into_iter().collect(),
+        env,
+    ))
+}
+
+fn stream_map_step(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    let f = pop_front!(list)?;
+    let mut cars = List::new();
+    let mut cdrs = vec![f.clone()];
+    for stream in list {
+        match force_stream(stream)? {
+            Some((car, cdr)) => {
+                cars.push_back(force(List::from([car]), env)?);
+                cdrs.push(cdr);
+            }
+            None => return Ok(stream_null()),
+        }
+    }
+    let car = evaluator::apply(&f, cars, env)?;
+    let cdr = promise::lazy_call(stream_map_step, cdrs, env);
+    Ok(stream_pair(promise::make(PromiseState::Done(car)), cdr))
+}
+
+pub fn stream_filter(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let pred = pop_front!(list)?;
+    let stream = pop_front!(list)?;
+    Ok(promise::lazy_call(
+        stream_filter_step,
+        vec![pred, stream],
+        env,
+    ))
+}
+
+fn stream_filter_step(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    let pred = pop_front!(list)?;
+    let mut stream = pop_front!(list)?;
+    // Skip over the elements left out in a loop, not by recursion
+    while let Some((car, cdr)) = force_stream(stream)? {
+        let val = force(List::from([car]), env)?;
+        if as_bool(&evaluator::apply(&pred, List::from([val.clone()]), env)?) {
+            let cdr = promise::lazy_call(stream_filter_step, vec![pred, cdr], env);
+            return Ok(stream_pair(promise::make(PromiseState::Done(val)), cdr));
+        }
+        stream = cdr;
+    }
+    Ok(stream_null())
+}
+
+// (stream-take n stream) is the stream of the first n elements
+pub fn stream_take(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let n = pop_front!(list)?;
+    as_count(n.clone())?;
+    let stream = pop_front!(list)?;
+    Ok(promise::lazy_call(stream_take_step, vec![n, stream], env))
+}
+
+fn stream_take_step(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    let n = as_count(pop_front!(list)?)?;
+    let stream = pop_front!(list)?;
+    if n == 0 {
+        return Ok(stream_null());
+    }
+    match force_stream(stream)? {
+        Some((car, cdr)) => {
+            let rest = vec![atom_num!(n as i64 - 1), cdr];
+            Ok(stream_pair(
+                car,
+                promise::lazy_call(stream_take_step, rest, env),
+            ))
+        }
+        None => Ok(stream_null()),
+    }
+}
+
+// (make-parameter value [converter])
+pub fn make_parameter(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    if list.is_empty() || list.len() > 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let value = pop_front!(list)?;
+    let converter = list.pop_front();
+    let value = match &converter {
+        Some(f) => evaluator::apply(f, List::from([value]), env)?,
+        None => value,
+    };
+    Ok(Expr::Parameter(Rc::new(Parameter {
+        value: RefCell::new(value),
+        converter,
+    })))
+}
+
+// (parameterize ((param value) ...) body...) gives the parameters new
+// values while body runs. The old ones are back however it is left,
+// by returning, by an error or by escaping to a continuation.
+pub fn parameterize(mut list: List, env: &Env) -> Result<Tail, EvalError> {
+    pop_and_check_nil(&mut list)?;
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+
+    let mut params = vec![];
+    for (param, value) in values_bindings(pop_front!(list)?)? {
+        let param = match eval_expr(param, env)? {
+            Expr::Parameter(p) => p,
+            e => return Err(EvalError::ExprTypeMismatch("parameter".to_string(), e)),
+        };
+        let value = eval_expr(value, env)?;
+        let value = match &param.converter {
+            Some(f) => evaluator::apply(f, List::from([value]), env)?,
+            None => value,
+        };
+        params.push((param, value));
+    }
+
+    for (param, value) in params.iter_mut() {
+        std::mem::swap(&mut *param.value.borrow_mut(), value);
+    }
+    // The body is not in tail position, the old values have to be restored.
+    // It gets a frame of its own, as a let body does.
+    let frame = Env::new(Some(env.clone()));
+    let mut res = Ok(atom_nil!());
+    for expr in list {
+        res = eval_expr(expr, &frame);
+        if res.is_err() {
+            break;
+        }
+    }
+    for (param, value) in params.iter_mut().rev() {
+        std::mem::swap(&mut *param.value.borrow_mut(), value);
+    }
+    res.map(Tail::Return)
+}
+
+/*
+ * Output, in the two styles: `display' writes strings and characters as
+ * their contents, `write' as literals that read back to them. Text goes
+ * to the port given last, or else to the current output port.
+ */
+
+fn as_port(expr: Expr) -> Result<Rc<Port>, EvalError> {
+    match expr {
+        Expr::Port(p) => Ok(p),
+        e => Err(EvalError::ExprTypeMismatch("port".to_string(), e)),
+    }
+}
+
+fn output_port(port: Option<Expr>) -> Result<Rc<Port>, EvalError> {
+    let port = match port {
+        Some(port) => port,
+        None => port::current_output().value.borrow().clone(),
+    };
+    match port {
+        Expr::Port(p) if p.is_output() => Ok(p),
+        e => Err(EvalError::ExprTypeMismatch("output port".to_string(), e)),
+    }
+}
+
+fn input_port(port: Expr) -> Result<Rc<Port>, EvalError> {
+    match port {
+        Expr::Port(p) if p.is_input() => Ok(p),
+        e => Err(EvalError::ExprTypeMismatch("input port".to_string(), e)),
+    }
+}
+
+// Writes `text' to the port left in `list', nothing is left for the
+// REPL to print
+fn print(text: &str, mut list: List) -> Result<Expr, EvalError> {
+    if list.len() > 1 {
+        return Err(EvalError::WrongNumOfArgs(1, list.len()));
+    }
+    output_port(list.pop_front())?.write_str(text);
+    Ok(values_from(List::new()))
+}
+
+pub fn display(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let obj = pop_front!(list)?;
+    print(&Displayed(&obj).to_string(), list)
+}
+
+pub fn write(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let obj = pop_front!(list)?;
+    print(&obj.to_string(), list)
+}
+
+pub fn newline(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    print("\n", list)
+}
+
+pub fn write_string(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let s = as_string(pop_front!(list)?)?;
+    print(&s, list)
+}
+
+pub fn write_char(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let c = as_char(pop_front!(list)?)?;
+    print(&c.to_string(), list)
+}
+
+/*
+ * String ports
+ */
+
+pub fn open_output_string(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 0)?;
+    Ok(Expr::Port(Rc::new(Port::OutputString(RefCell::new(
+        String::new(),
+    )))))
+}
+
+// Everything written to the port so far
+pub fn get_output_string(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    match pop_front!(list)? {
+        Expr::Port(p) => match &*p {
+            Port::OutputString(buf) => Ok(atom_str!(buf.borrow().clone())),
+            _ => Err(EvalError::ExprTypeMismatch(
+                "string output port".to_string(),
+                Expr::Port(p.clone()),
+            )),
+        },
+        e => Err(EvalError::ExprTypeMismatch(
+            "string output port".to_string(),
+            e,
+        )),
+    }
+}
+
+pub fn open_input_string(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(Expr::Port(Rc::new(Port::InputString {
+        text: as_string(pop_front!(list)?)?,
+        pos: Cell::new(0),
+    })))
+}
+
+fn char_or_eof(c: Option<char>) -> Expr {
+    expr_atom!(c.map_or(Atom::Eof, Atom::Char))
+}
+
+fn string_or_eof(s: Option<String>) -> Expr {
+    s.map_or(expr_atom!(Atom::Eof), |s| atom_str!(s))
+}
+
+pub fn read_char(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(char_or_eof(input_port(pop_front!(list)?)?.read_char()))
+}
+
+pub fn peek_char(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(char_or_eof(input_port(pop_front!(list)?)?.peek_char()))
+}
+
+pub fn read_line(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(string_or_eof(input_port(pop_front!(list)?)?.read_line()))
+}
+
+// (read-string k port)
+pub fn read_string(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let k = as_count(pop_front!(list)?)?;
+    Ok(string_or_eof(input_port(pop_front!(list)?)?.read_string(k)))
+}
+
+pub fn eof_object(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 0)?;
+    Ok(expr_atom!(Atom::Eof))
+}
+
+pub fn eof_object_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(
+        matches!(pop_front!(list)?, Expr::Atom(a) if *a == Atom::Eof)
+    ))
+}
+
+pub fn port_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(matches!(pop_front!(list)?, Expr::Port(_))))
+}
+
+pub fn input_port_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(as_port(pop_front!(list)?)?.is_input()))
+}
+
+pub fn output_port_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(as_port(pop_front!(list)?)?.is_output()))
+}
+
+/*
+ * Characters
+ */
+
+fn as_char(expr: Expr) -> Result<char, EvalError> {
+    match expr {
+        Expr::Atom(a) => match *a {
+            Atom::Char(c) => Ok(c),
+            a => Err(EvalError::TypeMismatch("char".to_string(), a)),
+        },
+        e => Err(EvalError::ExprTypeMismatch("char".to_string(), e)),
+    }
+}
+
+fn char_of(expr: &Expr) -> Option<char> {
+    match expr {
+        Expr::Atom(a) => match **a {
+            Atom::Char(c) => Some(c),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn char_arg(mut list: List) -> Result<char, EvalError> {
+    check_args(&list, 1)?;
+    as_char(pop_front!(list)?)
+}
+
+// Case conversions that would give several chars, such as the
+// uppercase of `ß', leave the char as it is
+fn single(mut chars: impl Iterator<Item = char>, c: char) -> char {
+    match (chars.next(), chars.next()) {
+        (Some(mapped), None) => mapped,
+        _ => c,
+    }
+}
+
+fn upcase(c: char) -> char {
+    single(c.to_uppercase(), c)
+}
+
+fn downcase(c: char) -> char {
+    single(c.to_lowercase(), c)
+}
+
+pub fn char_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_bool!(char_of(&pop_front!(list)?).is_some()))
+}
+
+pub fn char_to_integer(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(atom_num!(char_arg(list)? as i64))
+}
+
+pub fn integer_to_char(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    let arg = pop_front!(list)?;
+    let c = u32::try_from(as_count(arg.clone())?)
+        .ok()
+        .and_then(char::from_u32);
+    match c {
+        Some(c) => Ok(expr_atom!(Atom::Char(c))),
+        None => Err(EvalError::ExprTypeMismatch(
+            "Unicode scalar value".to_string(),
+            arg,
+        )),
+    }
+}
+
+pub fn char_upcase(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(expr_atom!(Atom::Char(upcase(char_arg(list)?))))
+}
+
+pub fn char_downcase(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(expr_atom!(Atom::Char(downcase(char_arg(list)?))))
+}
+
+pub fn char_foldcase(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(expr_atom!(Atom::Char(downcase(char_arg(list)?))))
+}
+
+pub fn char_alphabetic_p(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(atom_bool!(char_arg(list)?.is_alphabetic()))
+}
+
+pub fn char_numeric_p(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(atom_bool!(char_arg(list)?.is_numeric()))
+}
+
+pub fn char_whitespace_p(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(atom_bool!(char_arg(list)?.is_whitespace()))
+}
+
+pub fn char_upper_case_p(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(atom_bool!(char_arg(list)?.is_uppercase()))
+}
+
+pub fn char_lower_case_p(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    Ok(atom_bool!(char_arg(list)?.is_lowercase()))
+}
+
+// The value of a decimal digit, #f for any other char
+pub fn digit_value(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    match char_arg(list)?.to_digit(10) {
+        Some(d) => Ok(atom_num!(d as i64)),
+        None => Ok(atom_bool!(false)),
+    }
+}
+
+// Like `compare', the -ci versions fold the case of every char first
+fn compare_chars(list: List, fold: bool, cmp: fn(Ordering) -> bool) -> Result<Expr, EvalError> {
+    let chars = list
+        .into_iter()
+        .map(|c| as_char(c).map(|c| if fold { downcase(c) } else { c }))
+        .collect::<Result<Vec<_>, _>>()?;
+    if chars.is_empty() {
+        return Err(EvalError::WrongNumOfArgs(1, 0));
+    }
+
+    Ok(atom_bool!(chars.windows(2).all(|w| cmp(w[0].cmp(&w[1])))))
+}
+
+pub fn char_eq(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_chars(list, false, |o| o == Ordering::Equal)
+}
+
+pub fn char_lt(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_chars(list, false, |o| o == Ordering::Less)
+}
+
+pub fn char_gt(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_chars(list, false, |o| o == Ordering::Greater)
+}
+
+pub fn char_le(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_chars(list, false, |o| o != Ordering::Greater)
+}
+
+pub fn char_ge(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_chars(list, false, |o| o != Ordering::Less)
+}
+
+pub fn char_ci_eq(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_chars(list, true, |o| o == Ordering::Equal)
+}
+
+pub fn char_ci_lt(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_chars(list, true, |o| o == Ordering::Less)
+}
+
+pub fn char_ci_gt(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_chars(list, true, |o| o == Ordering::Greater)
+}
+
+pub fn char_ci_le(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_chars(list, true, |o| o != Ordering::Greater)
+}
+
+pub fn char_ci_ge(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_chars(list, true, |o| o != Ordering::Less)
+}
+
+/*
+ * Strings, indexed by char rather than by byte
+ */
+
+fn as_string(expr: Expr) -> Result<String, EvalError> {
+    match expr {
+        Expr::Atom(a) => match *a {
+            Atom::Str(s) => Ok(s.borrow().clone()),
+            a => Err(EvalError::TypeMismatch("string".to_string(), a)),
+        },
+        e => Err(EvalError::ExprTypeMismatch("string".to_string(), e)),
+    }
+}
+
+// The optional [start [end]] arguments left in `list', which default
+// to the whole of a string of `len' chars
+fn char_range(mut list: List, len: usize) -> Result<(usize, usize), EvalError> {
+    let start = match list.pop_front() {
+        Some(start) => as_count(start)?,
+        None => 0,
+    };
+    let end = match list.pop_front() {
+        Some(end) => as_count(end)?,
+        None => len,
+    };
+    if !list.is_empty() {
+        return Err(EvalError::WrongNumOfArgs(3, list.len() + 3));
+    }
+    if end > len {
+        return Err(EvalError::IndexOutOfRange(end, len));
+    }
+    if start > end {
+        return Err(EvalError::IndexOutOfRange(start, end));
+    }
+    Ok((start, end))
+}
+
+// Turns a byte offset into `s' into a char index
+fn char_index(s: &str, offset: usize) -> Expr {
+    atom_num!(s[..offset].chars().count() as i64)
+}
+
+// (radix) is 10 unless given, one of 2, 8, 10 or 16
+fn radix_arg(radix: Option<Expr>) -> Result<u32, EvalError> {
+    match radix {
+        None => Ok(10),
+        Some(expr) => match as_count(expr.clone())? {
+            r @ (2 | 8 | 10 | 16) => Ok(r as u32),
+            _ => Err(EvalError::ExprTypeMismatch(
+                "radix of 2, 8, 10 or 16".to_string(),
+                expr,
+            )),
+        },
+    }
+}
+
+pub fn string_p(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    let arg = pop_front!(list)?;
+    Ok(atom_bool!(
+        matches!(&arg, Expr::Atom(a) if matches!(**a, Atom::Str(_)))
+    ))
+}
+
+pub fn string(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let s = list.into_iter().map(as_char).collect::<Result<_, _>>()?;
+    Ok(atom_str!(s))
+}
+
+pub fn string_length(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    let s = as_shared_string(pop_front!(list)?)?;
+    let len = s.borrow().chars().count();
+    Ok(atom_num!(len as i64))
+}
+
+pub fn string_ref(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let s = as_shared_string(pop_front!(list)?)?;
+    let k = as_count(pop_front!(list)?)?;
+    let s = s.borrow();
+    match s.chars().nth(k) {
+        Some(c) => Ok(expr_atom!(Atom::Char(c))),
+        None => Err(EvalError::IndexOutOfRange(k, s.chars().count())),
+    }
+}
+
+// (substring s start [end])
+pub fn substring(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    if list.len() < 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let s = as_string(pop_front!(list)?)?;
+    let (start, end) = char_range(list, s.chars().count())?;
+    Ok(atom_str!(s.chars().skip(start).take(end - start).collect(),))
+}
+
+pub fn string_append(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let s = list
+        .into_iter()
+        .map(as_string)
+        .collect::<Result<String, _>>()?;
+    Ok(atom_str!(s))
+}
+
+// (string->list s [start [end]])
+pub fn string_to_list(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let s = as_string(pop_front!(list)?)?;
+    let (start, end) = char_range(list, s.chars().count())?;
+    let chars = s.chars().skip(start).take(end - start);
+    Ok(list_from(
+        chars.map(|c| expr_atom!(Atom::Char(c))).collect(),
+    ))
+}
+
+pub fn list_to_string(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    string(list_to_args(pop_front!(list)?)?, env)
+}
+
+pub fn string_to_symbol(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(expr_atom!(Atom::Symbol(as_string(pop_front!(list)?)?)))
+}
+
+pub fn symbol_to_string(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_str!(symbol_name(pop_front!(list)?)?))
+}
+
+// (number->string n [radix])
+pub fn number_to_string(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    if list.is_empty() || list.len() > 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let arg = pop_front!(list)?;
+    let radix = radix_arg(list.pop_front())?;
+    match as_num(arg.clone())?.to_string_radix(radix) {
+        Some(s) => Ok(atom_str!(s)),
+        None => Err(EvalError::ExprTypeMismatch("exact number".to_string(), arg)),
+    }
+}
+
+// (string->number s [radix]) is #f when s is not a number
+pub fn string_to_number(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    if list.is_empty() || list.len() > 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let s = as_string(pop_front!(list)?)?;
+    let radix = radix_arg(list.pop_front())?;
+    match Number::parse_radix(&s, radix) {
+        Some(n) => Ok(atom_num!(n)),
+        None => Ok(atom_bool!(false)),
+    }
+}
+
+pub fn string_upcase(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_str!(as_string(pop_front!(list)?)?.to_uppercase()))
+}
+
+pub fn string_downcase(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(atom_str!(as_string(pop_front!(list)?)?.to_lowercase()))
+}
+
+// (string-index s pred) is the index of the first char that is pred,
+// or satisfies it when pred is a procedure, #f if none does
+pub fn string_index(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let s = as_string(pop_front!(list)?)?;
+    let pred = pop_front!(list)?;
+    for (i, c) in s.chars().enumerate() {
+        let found = match char_of(&pred) {
+            Some(wanted) => c == wanted,
+            None => as_bool(&evaluator::apply(
+                &pred,
+                List::from([expr_atom!(Atom::Char(c))]),
+                env,
+            )?),
+        };
+        if found {
+            return Ok(atom_num!(i as i64));
+        }
+    }
+    Ok(atom_bool!(false))
+}
+
+// (string-contains s pattern) is the index where pattern first
+// appears in s, #f if it doesn't
+pub fn string_contains(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 2)?;
+    let s = as_string(pop_front!(list)?)?;
+    let pattern = as_string(pop_front!(list)?)?;
+    match s.find(&pattern) {
+        Some(offset) => Ok(char_index(&s, offset)),
+        None => Ok(atom_bool!(false)),
+    }
+}
+
+fn split_list<'a>(parts: impl Iterator<Item = &'a str>) -> Expr {
+    list_from(parts.map(|p| atom_str!(p.to_string())).collect())
+}
+
+// (string-split s [separator]) splits at every separator, a char or a
+// string, or at runs of whitespace when there is none
+pub fn string_split(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    if list.is_empty() || list.len() > 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let s = as_string(pop_front!(list)?)?;
+    match list.pop_front() {
+        None => Ok(split_list(s.split_whitespace())),
+        Some(sep) => match char_of(&sep) {
+            Some(c) => Ok(split_list(s.split(c))),
+            None => match as_string(sep)? {
+                sep if sep.is_empty() => Err(EvalError::ExprTypeMismatch(
+                    "non-empty separator".to_string(),
+                    atom_str!(sep),
+                )),
+                sep => Ok(split_list(s.split(sep.as_str()))),
+            },
+        },
+    }
+}
+
+// (string-join strings [separator]), a space unless given
+pub fn string_join(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    if list.is_empty() || list.len() > 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let strings = list_to_args(pop_front!(list)?)?
+        .into_iter()
+        .map(as_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    let sep = match list.pop_front() {
+        Some(sep) => as_string(sep)?,
+        None => " ".to_string(),
+    };
+    Ok(atom_str!(strings.join(&sep)))
+}
+
+fn as_shared_string(expr: Expr) -> Result<Rc<RefCell<String>>, EvalError> {
+    match expr {
+        Expr::Atom(a) => match *a {
+            Atom::Str(s) => Ok(s),
+            a => Err(EvalError::TypeMismatch("string".to_string(), a)),
+        },
+        e => Err(EvalError::ExprTypeMismatch("string".to_string(), e)),
+    }
+}
+
+// (make-string k [char]), filled with spaces unless told otherwise
+pub fn make_string(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    if list.is_empty() || list.len() > 2 {
+        return Err(EvalError::WrongNumOfArgs(2, list.len()));
+    }
+    let k = as_count(pop_front!(list)?)?;
+    let c = match list.pop_front() {
+        Some(c) => as_char(c)?,
+        None => ' ',
+    };
+    Ok(atom_str!(std::iter::repeat_n(c, k).collect()))
+}
+
+// (string-copy s [start [end]]) is a new string, changing it leaves s as
+// it was
+pub fn string_copy(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let s = as_string(pop_front!(list)?)?;
+    let (start, end) = char_range(list, s.chars().count())?;
+    Ok(atom_str!(s.chars().skip(start).take(end - start).collect()))
+}
+
+pub fn string_set(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 3)?;
+    let s = as_shared_string(pop_front!(list)?)?;
+    let k = as_count(pop_front!(list)?)?;
+    let c = as_char(pop_front!(list)?)?;
+
+    let mut s = s.borrow_mut();
+    let (offset, old) = match s.char_indices().nth(k) {
+        Some(found) => found,
+        None => return Err(EvalError::IndexOutOfRange(k, s.chars().count())),
+    };
+    s.replace_range(offset..offset + old.len_utf8(), c.encode_utf8(&mut [0; 4]));
+    Ok(values_from(List::new()))
+}
+
+// (string-fill! s char [start [end]])
+pub fn string_fill(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let s = as_shared_string(pop_front!(list)?)?;
+    let c = as_char(pop_front!(list)?)?;
+    let mut chars: Vec<char> = s.borrow().chars().collect();
+    let (start, end) = char_range(list, chars.len())?;
+
+    chars[start..end].fill(c);
+    *s.borrow_mut() = chars.into_iter().collect();
+    Ok(values_from(List::new()))
+}
+
+// (string-copy! to at from [start [end]]) copies the chars of from into
+// to, starting at index at. Both can be the same string.
+pub fn string_copy_to(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    if list.len() < 3 {
+        return Err(EvalError::WrongNumOfArgs(3, list.len()));
+    }
+    let to = as_shared_string(pop_front!(list)?)?;
+    let at = as_count(pop_front!(list)?)?;
+    let from: Vec<char> = as_string(pop_front!(list)?)?.chars().collect();
+    let (start, end) = char_range(list, from.len())?;
+
+    let mut chars: Vec<char> = to.borrow().chars().collect();
+    let last = at + (end - start);
+    if last > chars.len() {
+        return Err(EvalError::IndexOutOfRange(last, chars.len()));
+    }
+    chars.splice(at..last, from[start..end].iter().copied());
+    *to.borrow_mut() = chars.into_iter().collect();
+    Ok(values_from(List::new()))
+}
+
+// Like `compare', for strings in lexicographic order
+fn compare_strings(list: List, cmp: fn(Ordering) -> bool) -> Result<Expr, EvalError> {
+    let strings = list
+        .into_iter()
+        .map(as_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    if strings.is_empty() {
+        return Err(EvalError::WrongNumOfArgs(1, 0));
+    }
+
+    Ok(atom_bool!(strings.windows(2).all(|w| cmp(w[0].cmp(&w[1])))))
+}
+
+pub fn string_eq(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_strings(list, |o| o == Ordering::Equal)
+}
+
+pub fn string_lt(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_strings(list, |o| o == Ordering::Less)
+}
+
+pub fn string_gt(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_strings(list, |o| o == Ordering::Greater)
+}
+
+pub fn string_le(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_strings(list, |o| o != Ordering::Greater)
+}
+
+pub fn string_ge(list: List, _env: &Env) -> Result<Expr, EvalError> {
+    compare_strings(list, |o| o != Ordering::Less)
+}
+
+pub fn gensym(mut list: List, _env: &Env) -> Result<Expr, EvalError> {
+    let prefix = match list.len() {
+        0 => "g".to_string(),
+        1 => symbol_name(pop_front!(list)?)?,
+        n => return Err(EvalError::WrongNumOfArgs(1, n)),
+    };
+    Ok(expr_atom!(Atom::Uninterned(macros::gensym(&prefix))))
+}
+
+// Expands `form' once if it is a macro use, telling whether it was one
+fn expand_once(form: Expr, env: &Env) -> Result<(Expr, bool), EvalError> {
+    let head = match &form {
+        Expr::List(l) => match l.front() {
+            Some(Expr::Atom(a)) => match &**a {
+                Atom::Symbol(s) => env.get(s).ok(),
+                Atom::Uninterned(s) => env.get(&macros::uninterned_key(s)).ok(),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    };
+    match head {
+        Some(Expr::Macro(m)) => Ok((m.expand(form, &HashSet::new(), env)?, true)),
+        _ => Ok((form, false)),
+    }
+}
+
+pub fn macroexpand_1(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    Ok(expand_once(pop_front!(list)?, env)?.0)
+}
+
+// Expands the form until it is no longer a macro use, its subforms are
+// left as they are
+pub fn macroexpand(mut list: List, env: &Env) -> Result<Expr, EvalError> {
+    check_args(&list, 1)?;
+    let mut form = pop_front!(list)?;
+    loop {
+        match expand_once(form, env)? {
+            (expanded, true) => form = expanded,
+            (expanded, false) => return Ok(expanded),
+        }
+    }
+}
+
+/*
+fn pop_back(list: &mut List) -> Result<Expr, EvalError> {
+    list.pop_back().ok_or(EvalError::EmptyList)
+}
+
+fn pop_front(list: &mut List) -> Result<Expr, EvalError> {
+    list.pop_front().ok_or(EvalError::EmptyList)
+}
+*/
+
+fn pop_and_check_nil(list: &mut List) -> Result<Expr, EvalError> {
+    match list.pop_back() {
+        Some(expr) => match expr_is_nil(&expr) {
+            true => Ok(expr),
+            false => Err(EvalError::DottedList),
+        },
+        None => Err(EvalError::EmptyList),
+    }
+}
+
+// The name a symbol is bound under
+fn as_symbol(expr: Expr) -> Result<String, EvalError> {
+    match expr {
+        Expr::Atom(a) => match *a {
+            Atom::Symbol(s) => Ok(s),
+            Atom::Uninterned(s) => Ok(macros::uninterned_key(&s)),
+            a => Err(EvalError::TypeMismatch("symbol".to_string(), a)),
+        },
+        e => Err(EvalError::ExprTypeMismatch("symbol".to_string(), e)),
+    }
+}
+
+// The name a symbol is written with
+fn symbol_name(expr: Expr) -> Result<String, EvalError> {
+    match expr {
+        Expr::Atom(a) => match *a {
+            Atom::Symbol(s) | Atom::Uninterned(s) => Ok(s),
+            a => Err(EvalError::TypeMismatch("symbol".to_string(), a)),
+        },
+        e => Err(EvalError::ExprTypeMismatch("symbol".to_string(), e)),
+    }
+}
+
+fn as_bool(expr: &Expr) -> bool {
+    if let Expr::Atom(a) = expr {
+        match **a {
+            Atom::Bool(b) => b,
+            _ => true,
+        }
+    } else {
+        true
     }
-    Ok(atoms)
 }
 
 pub fn inspect(_: List, env: &Env) -> Result<Expr, EvalError> {
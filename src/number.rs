@@ -0,0 +1,336 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// Exact integers stay as fixnums while they fit in an i64,
+// once an operation overflows they are promoted to bignums.
+// Numbers are always kept in their simplest exact form: a bignum
+// never holds a value that would fit in a fixnum, and a rational
+// never has a denominator of one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Number {
+    Fixnum(i64),
+    Bignum(BigInt),
+    Rational(BigRational),
+    Real(f64),
+}
+
+// Exponents are capped so `#e1e999999999` doesn't take forever
+const MAX_EXACT_EXPONENT: i64 = 4096;
+
+impl Number {
+    fn to_bigint(&self) -> BigInt {
+        match self {
+            Number::Fixnum(n) => BigInt::from(*n),
+            Number::Bignum(n) => n.clone(),
+            Number::Rational(r) => r.to_integer(),
+            Number::Real(x) => BigInt::from(*x as i64),
+        }
+    }
+
+    fn to_rational(&self) -> BigRational {
+        match self {
+            Number::Rational(r) => r.clone(),
+            other => BigRational::from_integer(other.to_bigint()),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Fixnum(n) => *n as f64,
+            Number::Bignum(n) => n.to_f64().unwrap_or(f64::NAN),
+            Number::Rational(r) => r.to_f64().unwrap_or(f64::NAN),
+            Number::Real(x) => *x,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(self, Number::Real(_))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Fixnum(n) => *n == 0,
+            Number::Bignum(n) => n.is_zero(),
+            Number::Rational(r) => r.is_zero(),
+            Number::Real(x) => *x == 0.0,
+        }
+    }
+
+    // `None` for infinities and NaN, which have no exact counterpart
+    pub fn to_exact(&self) -> Option<Number> {
+        match self {
+            Number::Real(x) => BigRational::from_float(*x).map(Number::from),
+            other => Some(other.clone()),
+        }
+    }
+
+    pub fn to_inexact(&self) -> Number {
+        Number::Real(self.to_f64())
+    }
+
+    // Numeric ordering regardless of exactness, `None` when NaN is involved.
+    // Finite reals are compared exactly so the ordering stays transitive.
+    pub fn num_cmp(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Fixnum(a), Number::Fixnum(b)) => Some(a.cmp(b)),
+            (Number::Real(a), Number::Real(b)) => a.partial_cmp(b),
+            _ => match (self.to_exact(), other.to_exact()) {
+                (Some(a), Some(b)) => Some(a.to_rational().cmp(&b.to_rational())),
+                _ => self.to_f64().partial_cmp(&other.to_f64()),
+            },
+        }
+    }
+
+    // Exact division when both operands are exact, `None` when dividing
+    // an exact number by exact zero
+    pub fn checked_div(&self, other: &Number) -> Option<Number> {
+        match (self, other) {
+            (Number::Real(_), _) | (_, Number::Real(_)) => {
+                Some(Number::Real(self.to_f64() / other.to_f64()))
+            }
+            _ if other.is_zero() => None,
+            (Number::Fixnum(a), Number::Fixnum(b)) if a.checked_rem(*b) == Some(0) => {
+                Some(Number::Fixnum(a / b))
+            }
+            _ => Some(Number::from(self.to_rational() / other.to_rational())),
+        }
+    }
+
+    // Only exact numbers can be written in a radix other than 10
+    pub fn to_string_radix(&self, radix: u32) -> Option<String> {
+        match self {
+            _ if radix == 10 => Some(self.to_string()),
+            Number::Fixnum(_) | Number::Bignum(_) => Some(self.to_bigint().to_str_radix(radix)),
+            Number::Rational(r) => Some(format!(
+                "{}/{}",
+                r.numer().to_str_radix(radix),
+                r.denom().to_str_radix(radix)
+            )),
+            Number::Real(_) => None,
+        }
+    }
+
+    // Reads any numeric literal: integers, rationals and decimals with
+    // an optional exponent, `#x`, `#b`, `#o` and `#d` radix prefixes,
+    // `#e` and `#i` exactness prefixes and `+inf.0`, `-inf.0`, `+nan.0`
+    pub fn parse(s: &str) -> Option<Number> {
+        Number::parse_radix(s, 10)
+    }
+
+    // Like `parse`, numbers without a radix prefix are read in `radix`
+    pub fn parse_radix(s: &str, radix: u32) -> Option<Number> {
+        let mut radix = radix;
+        let mut exactness = None;
+        let mut rest = s;
+        while rest.starts_with('#') && rest.len() >= 2 {
+            match rest.as_bytes()[1].to_ascii_lowercase() {
+                b'x' => radix = 16,
+                b'b' => radix = 2,
+                b'o' => radix = 8,
+                b'd' => radix = 10,
+                b'e' => exactness = Some(true),
+                b'i' => exactness = Some(false),
+                _ => return None,
+            }
+            rest = &rest[2..];
+        }
+
+        match exactness {
+            Some(true) => match parse_decimal(rest, radix) {
+                Some((mantissa, exponent)) => {
+                    Some(Number::from(exact_decimal(mantissa, exponent)?))
+                }
+                None => parse_real(rest, radix)?.to_exact(),
+            },
+            Some(false) => Some(parse_real(rest, radix)?.to_inexact()),
+            None => parse_real(rest, radix),
+        }
+    }
+}
+
+fn split_sign(s: &str) -> (bool, &str) {
+    match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    }
+}
+
+fn parse_uint(digits: &str, radix: u32) -> Option<BigInt> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    BigInt::parse_bytes(digits.as_bytes(), radix)
+}
+
+fn parse_int(s: &str, radix: u32) -> Option<BigInt> {
+    let (negative, digits) = split_sign(s);
+    let n = parse_uint(digits, radix)?;
+    Some(if negative { -n } else { n })
+}
+
+// Splits a decimal literal into its digits and power of ten,
+// so `-1.25e2` becomes (-125, 0)
+fn parse_decimal(s: &str, radix: u32) -> Option<(BigInt, i64)> {
+    if radix != 10 {
+        return None;
+    }
+    let (negative, s) = split_sign(s);
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], s[i + 1..].parse::<i64>().ok()?),
+        None => (s, 0),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, ""),
+    };
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+
+    let digits = format!("{}{}", int, frac);
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let n = BigInt::parse_bytes(digits.as_bytes(), 10)?;
+    let n = if negative { -n } else { n };
+    Some((n, exponent.checked_sub(frac.len() as i64)?))
+}
+
+fn exact_decimal(mantissa: BigInt, exponent: i64) -> Option<BigRational> {
+    if exponent.abs() > MAX_EXACT_EXPONENT {
+        return None;
+    }
+    let scale = num_traits::pow(BigInt::from(10), exponent.unsigned_abs() as usize);
+    Some(if exponent < 0 {
+        BigRational::new(mantissa, scale)
+    } else {
+        BigRational::from_integer(mantissa * scale)
+    })
+}
+
+fn parse_real(s: &str, radix: u32) -> Option<Number> {
+    match s {
+        "+inf.0" => return Some(Number::Real(f64::INFINITY)),
+        "-inf.0" => return Some(Number::Real(f64::NEG_INFINITY)),
+        "+nan.0" | "-nan.0" => return Some(Number::Real(f64::NAN)),
+        _ => {}
+    }
+
+    if let Some(i) = s.find('/') {
+        let num = parse_int(&s[..i], radix)?;
+        let den = parse_uint(&s[i + 1..], radix)?;
+        if den.is_zero() {
+            return None;
+        }
+        return Some(Number::from(BigRational::new(num, den)));
+    }
+
+    if let Some(n) = parse_int(s, radix) {
+        return Some(Number::from(n));
+    }
+
+    // Decimals are inexact unless told otherwise
+    parse_decimal(s, radix)?;
+    s.parse::<f64>().ok().map(Number::Real)
+}
+
+impl From<i32> for Number {
+    fn from(n: i32) -> Self {
+        Number::Fixnum(n.into())
+    }
+}
+
+impl From<i64> for Number {
+    fn from(n: i64) -> Self {
+        Number::Fixnum(n)
+    }
+}
+
+impl From<f64> for Number {
+    fn from(x: f64) -> Self {
+        Number::Real(x)
+    }
+}
+
+impl From<BigInt> for Number {
+    fn from(n: BigInt) -> Self {
+        match n.to_i64() {
+            Some(n) => Number::Fixnum(n),
+            None => Number::Bignum(n),
+        }
+    }
+}
+
+impl From<BigRational> for Number {
+    fn from(r: BigRational) -> Self {
+        if r.denom().is_one() {
+            Number::from(r.to_integer())
+        } else {
+            Number::Rational(r)
+        }
+    }
+}
+
+// Inexact operands make the result inexact, then rationals take over
+// integers, which only become bignums once a fixnum overflows
+macro_rules! impl_checked_op {
+    ($trait:ident, $method:ident, $checked:ident) => {
+        impl $trait for Number {
+            type Output = Number;
+
+            fn $method(self, other: Number) -> Number {
+                match (&self, &other) {
+                    (Number::Fixnum(a), Number::Fixnum(b)) => match a.$checked(*b) {
+                        Some(n) => Number::Fixnum(n),
+                        None => Number::from(BigInt::from(*a).$method(BigInt::from(*b))),
+                    },
+                    (Number::Real(_), _) | (_, Number::Real(_)) => {
+                        Number::Real(self.to_f64().$method(other.to_f64()))
+                    }
+                    (Number::Rational(_), _) | (_, Number::Rational(_)) => {
+                        Number::from(self.to_rational().$method(other.to_rational()))
+                    }
+                    _ => Number::from(self.to_bigint().$method(other.to_bigint())),
+                }
+            }
+        }
+    };
+}
+
+impl_checked_op!(Add, add, checked_add);
+impl_checked_op!(Sub, sub, checked_sub);
+impl_checked_op!(Mul, mul, checked_mul);
+
+impl Neg for Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        match self {
+            Number::Real(x) => Number::Real(-x),
+            Number::Rational(r) => Number::Rational(-r),
+            n => Number::Fixnum(0) - n,
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Fixnum(n) => write!(f, "{}", n),
+            Number::Bignum(n) => write!(f, "{}", n),
+            Number::Rational(r) => write!(f, "{}", r),
+            Number::Real(x) if x.is_nan() => write!(f, "+nan.0"),
+            Number::Real(x) if x.is_infinite() => {
+                write!(f, "{}inf.0", if x.is_positive() { "+" } else { "-" })
+            }
+            // Debug keeps the decimal point and switches to
+            // scientific notation for very big or small numbers
+            Number::Real(x) => write!(f, "{:?}", x),
+        }
+    }
+}
@@ -1,18 +1,38 @@
-use crate::tokenizer::Token;
+use crate::tokenizer::{Span, Token};
+use std::error::Error;
 use std::fmt;
-use std::num;
 
 #[derive(Debug)]
 pub enum ParserErr {
     TokenNotFound(String),
-    ParseInt(num::ParseIntError),
     UnexpectedToken((Token, Vec<String>)),
-    UnclosedList,
+    UnclosedList(Span),
+    UnknownChar(Token),
+    BadEscape(Token),
+}
+
+impl ParserErr {
+    pub fn span(&self) -> Option<Span> {
+        match &self {
+            Self::UnexpectedToken((t, _)) => Some(t.span),
+            Self::UnclosedList(span) => Some(*span),
+            Self::UnknownChar(t) | Self::BadEscape(t) => Some(t.span),
+            _ => None,
+        }
+    }
+
+    // Error message followed by the offending source, when it is known
+    pub fn report(&self, source: &str) -> String {
+        match self.span() {
+            Some(span) => format!("{}\n{}", self, span.underline(source)),
+            None => self.to_string(),
+        }
+    }
 }
 
 impl fmt::Display for ParserErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fn format_vec(v: &Vec<String>) -> String {
+        fn format_vec(v: &[String]) -> String {
             let mut result = format!("`{}'", v[0]);
             for s in &v[1..] {
                 result = format!("{}, `{}'", result, s);
@@ -21,19 +41,24 @@ impl fmt::Display for ParserErr {
         }
         match &self {
             Self::TokenNotFound(msg) => write!(f, "{}", msg),
-            Self::ParseInt(e) => write!(f, "{}", e),
             Self::UnexpectedToken((t, v)) => {
                 write!(
                     f,
-                    "Expected any of {} but found `{}'",
+                    "{}: Expected any of {} but found `{}'",
+                    t.span.start,
                     format_vec(v),
                     t.value.as_str()
                 )
             }
-            Self::UnclosedList => write!(
+            Self::UnclosedList(span) => write!(
                 f,
-                "Unexpected EOF parsing cons, ')' may be missing (unclosed list)"
+                "{}: Unexpected EOF parsing cons, ')' may be missing (unclosed list)",
+                span.start
             ),
+            Self::UnknownChar(t) => {
+                write!(f, "{}: Unknown character `{}'", t.span.start, t.value)
+            }
+            Self::BadEscape(t) => write!(f, "{}: Bad escape sequence in string", t.span.start),
         }
     }
 }
@@ -54,13 +79,9 @@ macro_rules! unexpected_token {
 
 #[macro_export]
 macro_rules! unclosed_list {
-    () => {
-        ParserErr::UnclosedList
+    ( $span:expr ) => {
+        ParserErr::UnclosedList($span)
     };
 }
 
-// impl Error for ParserErr {
-//     fn source(&self) -> Option<&(dyn Error + 'static)> {
-//         unimplemented!();
-//     }
-// }
+impl Error for ParserErr {}
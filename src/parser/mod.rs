@@ -17,6 +17,7 @@
  * Value = ( let name Expression )
  *
  * Atom = String
+ *      | Char
  *      | Number
  *      | Bool
  *      | nil
@@ -24,11 +25,19 @@
  */
 use super::env::Env;
 use super::evaluator::error::EvalError;
-use super::tokenizer::{Token, TokenType};
+use super::evaluator::Tail;
+use super::macros::Macro;
+use super::number::Number;
+use super::port::Port;
+use super::promise::Promise;
+use super::tokenizer::{Position, Span, Token, TokenType};
 use crate::atom_nil;
+use crate::utils::{expr_is_nil, unshare};
 use std::boxed::Box;
+use std::cell::{Cell, RefCell};
 use std::collections::LinkedList;
 use std::fmt;
+use std::rc::Rc;
 
 #[macro_use]
 pub mod error;
@@ -39,34 +48,125 @@ use error::ParserErr;
 #[derive(PartialEq, Debug, Clone)]
 pub enum Expr {
     Atom(Box<Atom>),
-    List(Box<List>),
+    List(Rc<List>),
     Quote(Box<Expr>),
     Quasiquote(Box<Expr>),
     Unquote(Box<Expr>),
-    Lambda(Box<Lambda>),
+    UnquoteSplicing(Box<Expr>),
+    Lambda(Rc<Lambda>),
+    CaseLambda(Rc<Vec<Rc<Lambda>>>),
+    Macro(Rc<Macro>),
+    Continuation(Rc<Continuation>),
+    Condition(Rc<Condition>),
+    // Zero or several results, a single one is returned as itself
+    Values(Box<Vec<Expr>>),
+    Promise(Rc<RefCell<Promise>>),
+    Parameter(Rc<Parameter>),
+    Port(Rc<Port>),
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Atom {
-    Str(String),
-    Num(i32),
+    // Shared, so changes made by string-set! and the like are seen
+    // through every reference to the string
+    Str(Rc<RefCell<String>>),
+    Char(char),
+    Num(Number),
     Bool(bool),
     Symbol(String),
+    // Made by gensym, the same only as itself and never as a symbol read
+    Uninterned(String),
     Nil,
+    // What reading from a port gives once there is nothing left
+    Eof,
     Native(NativeEnc),
+    Syntax(SyntaxEnc),
 }
 
 // Linked list
 pub type List = LinkedList<Expr>;
 pub struct PrintableList(pub Box<List>);
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct Lambda {
+    pub name: Option<String>,
     pub args_list: Vec<String>,
+    // Missing optional arguments take the value of their default
+    pub optionals: Vec<(String, Expr)>,
+    pub rest: Option<String>,
     pub body: List,
     pub env: Env,
 }
 
+impl Lambda {
+    pub fn accepts(&self, n: usize) -> bool {
+        let required = self.args_list.len();
+        n >= required && (self.rest.is_some() || n <= required + self.optionals.len())
+    }
+
+    // How many arguments it takes, as told in arity errors
+    pub fn arity(&self) -> String {
+        let required = self.args_list.len();
+        match self.optionals.len() {
+            _ if self.rest.is_some() => format!("at least {}", required),
+            0 => required.to_string(),
+            n => format!("{} to {}", required, required + n),
+        }
+    }
+}
+
+// Lambdas are shared through an Rc, so two of them are equal
+// only when they are the very same closure
+impl std::cmp::PartialEq for Lambda {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// An escape continuation, captured by `call/cc`.
+///
+/// Calling it unwinds the evaluation back to where it was captured, which is
+/// only possible while that `call/cc` has not returned yet.
+#[derive(Debug)]
+pub struct Continuation {
+    pub active: Cell<bool>,
+}
+
+impl std::cmp::PartialEq for Continuation {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// An error object, made by `error` or out of a built-in error.
+#[derive(Debug)]
+pub struct Condition {
+    pub message: Expr,
+    pub irritants: List,
+}
+
+impl std::cmp::PartialEq for Condition {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// A parameter object, called with no arguments to get its value.
+///
+/// Values given to `make-parameter` and `parameterize` go through the
+/// converter first, when there is one.
+#[derive(Debug)]
+pub struct Parameter {
+    pub value: RefCell<Expr>,
+    pub converter: Option<Expr>,
+}
+
+impl std::cmp::PartialEq for Parameter {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
 pub type NativeFn = fn(List, &Env) -> Result<Expr, EvalError>;
 pub struct NativeEnc(pub NativeFn);
 
@@ -77,8 +177,8 @@ impl fmt::Debug for NativeEnc {
 }
 
 impl std::cmp::PartialEq for NativeEnc {
-    fn eq(&self, _: &Self) -> bool {
-        false
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::fn_addr_eq(self.0, other.0)
     }
 }
 
@@ -88,27 +188,126 @@ impl Clone for NativeEnc {
     }
 }
 
+// Special forms may leave an expression to be evaluated in tail position
+pub type SyntaxFn = fn(List, &Env) -> Result<Tail, EvalError>;
+pub struct SyntaxEnc(pub SyntaxFn);
+
+impl fmt::Debug for SyntaxEnc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<syntax>")
+    }
+}
+
+impl std::cmp::PartialEq for SyntaxEnc {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::fn_addr_eq(self.0, other.0)
+    }
+}
+
+impl Clone for SyntaxEnc {
+    fn clone(&self) -> Self {
+        SyntaxEnc(self.0)
+    }
+}
+
+// Characters written by name, as in `#\space'
+pub const CHAR_NAMES: [(&str, char); 10] = [
+    ("alarm", '\u{7}'),
+    ("backspace", '\u{8}'),
+    ("delete", '\u{7f}'),
+    ("escape", '\u{1b}'),
+    ("newline", '\n'),
+    ("null", '\0'),
+    ("nul", '\0'),
+    ("return", '\r'),
+    ("space", ' '),
+    ("tab", '\t'),
+];
+
 fn parse_str(t: &Token) -> Result<String, ParserErr> {
     // let t = tokens.pop().ok_or(token_not_found!("Token not found parsing str"))?;
     match t.ttype {
-        TokenType::Str => Ok(t.value.clone()),
+        // The tokenizer makes sure it is closed
+        TokenType::Str => Ok(t.value[1..t.value.len() - 1].to_string()),
         _ => Err(token_not_found!("<string>")),
     }
 }
 
-fn parse_num(t: &Token) -> Result<i32, ParserErr> {
+// The contents of a string literal with its escapes replaced: `\n',
+// `\t', `\x41;' and the like. A backslash at the end of a line skips
+// the line break and the indentation around it.
+fn unescape(s: &str) -> Option<String> {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'a' => res.push('\u{7}'),
+            'b' => res.push('\u{8}'),
+            't' => res.push('\t'),
+            'n' => res.push('\n'),
+            'r' => res.push('\r'),
+            c @ ('"' | '\\' | '|') => res.push(c),
+            'x' | 'X' => {
+                let mut hex = String::new();
+                loop {
+                    match chars.next()? {
+                        ';' => break,
+                        c => hex.push(c),
+                    }
+                }
+                res.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            }
+            c if c.is_whitespace() => {
+                let mut newline = c == '\n';
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' && !newline {
+                        newline = true;
+                    } else if c == '\n' || !c.is_whitespace() {
+                        break;
+                    }
+                    chars.next();
+                }
+                if !newline {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(res)
+}
+
+// `#\a', `#\space' or `#\x41'
+fn parse_char(t: &Token) -> Option<char> {
+    let name = t.value.strip_prefix("#\\")?;
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => return Some(c),
+        (Some('x'), Some(_)) => {
+            if let Some(c) = u32::from_str_radix(&name[1..], 16)
+                .ok()
+                .and_then(char::from_u32)
+            {
+                return Some(c);
+            }
+        }
+        _ => {}
+    }
+    CHAR_NAMES.iter().find(|(n, _)| *n == name).map(|(_, c)| *c)
+}
+
+fn parse_num(t: &Token) -> Result<Number, ParserErr> {
     // let t = tokens.pop().ok_or(token_not_found!("Token not found parsing num"))?;
 
     if t.ttype != TokenType::Other {
         return Err(token_not_found!("<number>"));
     }
 
-    let num = match t.value.parse::<i32>() {
-        Ok(n) => n,
-        Err(e) => return Err(error::ParserErr::ParseInt(e)),
-    };
-
-    Ok(num)
+    Number::parse(&t.value).ok_or_else(|| token_not_found!("<number>"))
 }
 
 fn parse_bool(t: &Token) -> Result<bool, ParserErr> {
@@ -134,7 +333,15 @@ fn parse_atom(tokens: &mut Vec<Token>) -> Result<Atom, ParserErr> {
 
     if t.ttype == TokenType::Str {
         let s = parse_str(&t)?;
-        return Ok(Atom::Str(s));
+        return unescape(&s)
+            .map(|s| Atom::Str(Rc::new(RefCell::new(s))))
+            .ok_or(ParserErr::BadEscape(t));
+    }
+
+    if t.ttype == TokenType::Char {
+        return parse_char(&t)
+            .map(Atom::Char)
+            .ok_or(ParserErr::UnknownChar(t));
     }
 
     let num = parse_num(&t);
@@ -153,7 +360,7 @@ fn parse_atom(tokens: &mut Vec<Token>) -> Result<Atom, ParserErr> {
     Ok(Atom::Symbol(t.value))
 }
 
-fn parse_cdr(tokens: &mut Vec<Token>) -> Result<Expr, ParserErr> {
+fn parse_cdr(tokens: &mut Vec<Token>, open: Span) -> Result<(Expr, Node, Position), ParserErr> {
     let t = tokens
         .pop()
         .ok_or_else(|| token_not_found!("Token not found parsing cons"))?;
@@ -162,20 +369,22 @@ fn parse_cdr(tokens: &mut Vec<Token>) -> Result<Expr, ParserErr> {
         return Err(unexpected_token!(t, "."));
     }
 
-    let expr = parse_expr(tokens)?;
-    let end = tokens.pop().ok_or_else(|| unclosed_list!())?;
+    let (expr, node) = parse_node(tokens)?;
+    let end = tokens.pop().ok_or(unclosed_list!(open))?;
 
     match end.ttype {
-        TokenType::Clc => Ok(expr),
+        TokenType::Clc => Ok((expr, node, end.span.end)),
         _ => Err(unexpected_token!(end, ")")),
     }
 }
 
-fn parse_list(tokens: &mut Vec<Token>) -> Result<List, ParserErr> {
+// The elements of a list along with their nodes, and where it ends
+fn parse_list(tokens: &mut Vec<Token>) -> Result<(List, Vec<Node>, Position), ParserErr> {
     let t = tokens
         .pop()
         .ok_or_else(|| token_not_found!("Token not found parsing list"))?;
     let mut list: LinkedList<Expr> = LinkedList::new();
+    let mut nodes = vec![];
 
     if t.ttype != TokenType::Opc {
         return Err(unexpected_token!(t, "("));
@@ -184,8 +393,7 @@ fn parse_list(tokens: &mut Vec<Token>) -> Result<List, ParserErr> {
     loop {
         let next = match tokens.last() {
             Some(next) => next,
-            // TODO: Give the position of the error
-            None => return Err(unclosed_list!()),
+            None => return Err(unclosed_list!(t.span)),
         };
 
         match next.ttype {
@@ -203,64 +411,96 @@ fn parse_list(tokens: &mut Vec<Token>) -> Result<List, ParserErr> {
                         "<value>"
                     ));
                 }
-                let cdr = parse_cdr(tokens)?;
-                list.push_back(cdr);
-                return Ok(list);
+                // `(a . (b c))' is the same list as `(a b c)'
+                let (cdr, node, end) = parse_cdr(tokens, t.span)?;
+                match cdr {
+                    Expr::List(l) => {
+                        list.append(&mut unshare(l));
+                        nodes.extend(node.children);
+                    }
+                    nil if expr_is_nil(&nil) => list.push_back(nil),
+                    cdr => {
+                        list.push_back(cdr);
+                        nodes.push(node);
+                    }
+                }
+                return Ok((list, nodes, end));
             }
 
             TokenType::Clc => {
-                tokens.pop().unwrap();
+                let end = tokens.pop().unwrap().span.end;
                 list.push_back(atom_nil!());
-                return Ok(list);
+                return Ok((list, nodes, end));
             }
 
             _ => {
-                let res = parse_expr(tokens);
-                if let Ok(res) = res {
-                    list.push_back(res);
-                } else {
-                    return Err(res.unwrap_err());
-                }
+                let (expr, node) = parse_node(tokens)?;
+                list.push_back(expr);
+                nodes.push(node);
             }
         }
     }
 }
 
-pub fn parse_expr(tokens: &mut Vec<Token>) -> Result<Expr, ParserErr> {
+// A quote and friends, `'' on its own quotes ()
+fn parse_quoted(tokens: &mut Vec<Token>) -> Result<(Expr, Node), ParserErr> {
+    let t = tokens.pop().unwrap();
+    let (expr, node) = match tokens.is_empty() {
+        true => (atom_nil!(), Node::leaf(t.span, None)),
+        false => parse_node(tokens)?,
+    };
+    let span = Span {
+        start: t.span.start,
+        end: node.span.end,
+    };
+    let expr = Box::new(expr);
+    let expr = match t.ttype {
+        TokenType::Quo => Expr::Quote(expr),
+        TokenType::Unquo => Expr::Unquote(expr),
+        TokenType::UnquoSplice => Expr::UnquoteSplicing(expr),
+        _ => Expr::Quasiquote(expr),
+    };
+    Ok((
+        expr,
+        Node {
+            span,
+            atom: None,
+            children: vec![node],
+        },
+    ))
+}
+
+fn parse_node(tokens: &mut Vec<Token>) -> Result<(Expr, Node), ParserErr> {
     let t = match tokens.last() {
         Some(t) => t,
-        None => return Ok(atom_nil!()),
+        None => return Err(token_not_found!("Token not found parsing expression")),
     };
 
     let res = match t.ttype {
         TokenType::Opc => {
-            let l = parse_list(tokens)?;
-            if l.len() <= 1 {
+            let start = t.span.start;
+            let (l, children, end) = parse_list(tokens)?;
+            let expr = if l.len() <= 1 {
                 atom_nil!()
             } else {
-                Expr::List(Box::new(l))
-            }
+                Expr::List(Rc::new(l))
+            };
+            let node = Node {
+                span: Span { start, end },
+                atom: None,
+                children,
+            };
+            (expr, node)
         }
 
-        TokenType::Quo => {
-            tokens.pop().unwrap();
-            let q = parse_expr(tokens)?;
-            Expr::Quote(Box::new(q))
-        }
-        TokenType::Unquo => {
-            tokens.pop().unwrap();
-            let q = parse_expr(tokens)?;
-            Expr::Unquote(Box::new(q))
-        }
-        TokenType::Quasi => {
-            tokens.pop().unwrap();
-            let q = parse_expr(tokens)?;
-            Expr::Quasiquote(Box::new(q))
+        TokenType::Quo | TokenType::Unquo | TokenType::UnquoSplice | TokenType::Quasi => {
+            parse_quoted(tokens)?
         }
 
-        TokenType::Str | TokenType::Other => {
+        TokenType::Str | TokenType::Char | TokenType::Other => {
+            let span = t.span;
             let a = parse_atom(tokens)?;
-            Expr::Atom(Box::new(a))
+            (Expr::Atom(Box::new(a.clone())), Node::leaf(span, Some(a)))
         }
 
         _ => {
@@ -281,6 +521,13 @@ pub fn parse_expr(tokens: &mut Vec<Token>) -> Result<Expr, ParserErr> {
     Ok(res)
 }
 
+pub fn parse_expr(tokens: &mut Vec<Token>) -> Result<Expr, ParserErr> {
+    if tokens.is_empty() {
+        return Ok(atom_nil!());
+    }
+    Ok(parse_node(tokens)?.0)
+}
+
 pub fn parse(tokens: &mut Vec<Token>) -> Result<Expr, ParserErr> {
     let expr = parse_expr(tokens)?;
 
@@ -290,3 +537,62 @@ pub fn parse(tokens: &mut Vec<Token>) -> Result<Expr, ParserErr> {
         Err(unexpected_token!(tokens.pop().unwrap(), "EOF"))
     }
 }
+
+/// Where a parsed expression was read from.
+///
+/// Lists have a node for each of their elements, quotes for what they
+/// quote, so errors can point at the subexpression they come from.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub span: Span,
+    // Only for atoms
+    pub atom: Option<Atom>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    fn leaf(span: Span, atom: Option<Atom>) -> Node {
+        Node {
+            span,
+            atom,
+            children: vec![],
+        }
+    }
+
+    /// The first node, in source order, `pred` holds for.
+    pub fn find(&self, pred: &dyn Fn(&Node) -> bool) -> Option<&Node> {
+        if pred(self) {
+            return Some(self);
+        }
+        self.children.iter().find_map(|c| c.find(pred))
+    }
+}
+
+// Top-level expression along with the source it was read from
+#[derive(Debug)]
+pub struct Spanned {
+    pub expr: Expr,
+    pub span: Span,
+    pub node: Node,
+}
+
+// Parses every top-level expression of a program, in order
+pub fn parse_program(tokens: &mut Vec<Token>) -> Result<Vec<Spanned>, ParserErr> {
+    // Tokens are stored in reverse, so the last token consumed by a form
+    // is the one right above the remaining ones
+    let mut forms = Vec::new();
+    while !tokens.is_empty() {
+        let (expr, node) = parse_node(tokens)?;
+        forms.push(Spanned {
+            expr,
+            span: node.span,
+            node,
+        });
+    }
+    Ok(forms)
+}
+
+pub fn parse_all(tokens: &mut Vec<Token>) -> Result<Vec<Expr>, ParserErr> {
+    let forms = parse_program(tokens)?;
+    Ok(forms.into_iter().map(|f| f.expr).collect())
+}
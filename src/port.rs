@@ -0,0 +1,110 @@
+use super::parser::{Expr, Parameter};
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::rc::Rc;
+
+/// Where `display`, `write` and the other output procedures send their
+/// text, or where the input ones read it from.
+#[derive(Debug)]
+pub enum Port {
+    // Standard output
+    Console,
+    // Collects what is written into it, for get-output-string
+    OutputString(RefCell<String>),
+    // Reads the chars of a string, from `pos' on
+    InputString { text: String, pos: Cell<usize> },
+}
+
+// Ports are shared through an Rc, so two of them are equal
+// only when they are the very same port
+impl std::cmp::PartialEq for Port {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+thread_local! {
+    static CURRENT_OUTPUT: Rc<Parameter> = Rc::new(Parameter {
+        value: RefCell::new(Expr::Port(Rc::new(Port::Console))),
+        converter: None,
+    });
+}
+
+/// The `current-output-port` parameter, which `parameterize` can point
+/// at a string port to collect what is printed.
+pub fn current_output() -> Rc<Parameter> {
+    CURRENT_OUTPUT.with(Rc::clone)
+}
+
+impl Port {
+    pub fn is_input(&self) -> bool {
+        matches!(self, Port::InputString { .. })
+    }
+
+    pub fn is_output(&self) -> bool {
+        !self.is_input()
+    }
+
+    // Output ports only
+    pub fn write_str(&self, s: &str) {
+        match self {
+            Port::Console => {
+                print!("{}", s);
+                let _ = std::io::stdout().flush();
+            }
+            Port::OutputString(buf) => buf.borrow_mut().push_str(s),
+            Port::InputString { .. } => {}
+        }
+    }
+
+    // What is left to read, empty for output ports
+    fn rest(&self) -> &str {
+        match self {
+            Port::InputString { text, pos } => &text[pos.get()..],
+            _ => "",
+        }
+    }
+
+    fn advance(&self, bytes: usize) {
+        if let Port::InputString { pos, .. } = self {
+            pos.set(pos.get() + bytes);
+        }
+    }
+
+    pub fn peek_char(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    pub fn read_char(&self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.advance(c.len_utf8());
+        Some(c)
+    }
+
+    // Up to the end of the line, the line break is read but not returned
+    pub fn read_line(&self) -> Option<String> {
+        let rest = self.rest();
+        if rest.is_empty() {
+            return None;
+        }
+        let (line, len) = match rest.find('\n') {
+            Some(i) => (&rest[..i], i + 1),
+            None => (rest, rest.len()),
+        };
+        let line = line.strip_suffix('\r').unwrap_or(line).to_string();
+        self.advance(len);
+        Some(line)
+    }
+
+    // Up to `k' chars, fewer if the port runs out first
+    pub fn read_string(&self, k: usize) -> Option<String> {
+        let rest = self.rest();
+        if rest.is_empty() {
+            return None;
+        }
+        let len = rest.char_indices().nth(k).map_or(rest.len(), |(i, _)| i);
+        let s = rest[..len].to_string();
+        self.advance(len);
+        Some(s)
+    }
+}
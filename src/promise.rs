@@ -0,0 +1,128 @@
+use super::env::Env;
+use super::evaluator::error::EvalError;
+use super::evaluator::eval_expr;
+use super::parser::{Atom, Expr, List, NativeEnc, NativeFn};
+use crate::atom_nil;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// What a promise is at: still to be computed, or its value.
+#[derive(Debug, Clone)]
+pub enum PromiseState {
+    Done(Expr),
+    // From delay, the expression gives the value
+    Delayed(Expr, Env),
+    // From delay-force, the expression gives another promise to force
+    Lazy(Expr, Env),
+}
+
+/// A value computed the first time it is forced, and remembered after.
+///
+/// The state is kept in a box of its own, which forcing a delay-force
+/// promise shares with the promise it gave, so whichever of them is
+/// forced the value is computed only once.
+#[derive(Debug)]
+pub struct Promise {
+    state: Rc<RefCell<PromiseState>>,
+}
+
+// Promises are shared through an Rc, so two of them are equal
+// only when they are the very same promise
+impl std::cmp::PartialEq for Promise {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// A forced stream is a long chain of promises, which would be dropped by
+// recursing down the chain and could overflow the stack. Instead the
+// promises held by this one are unlinked and dropped in a loop.
+impl Drop for PromiseState {
+    fn drop(&mut self) {
+        let mut pending = vec![];
+        self.take_promises(&mut pending);
+        while let Some(p) = pending.pop() {
+            if let Ok(p) = Rc::try_unwrap(p) {
+                if let Ok(state) = Rc::try_unwrap(p.into_inner().state) {
+                    state.into_inner().take_promises(&mut pending);
+                }
+            }
+        }
+    }
+}
+
+impl PromiseState {
+    fn take_promises(&mut self, pending: &mut Vec<Rc<RefCell<Promise>>>) {
+        let list = match self {
+            PromiseState::Done(Expr::List(l)) => l,
+            PromiseState::Lazy(Expr::List(l), _) => l,
+            _ => return,
+        };
+        // A list held elsewhere too is left alone, its last holder drops it
+        let list = match Rc::get_mut(list) {
+            Some(list) => list,
+            None => return,
+        };
+        for expr in list.iter_mut() {
+            let expr = match expr {
+                Expr::Quote(q) => &mut **q,
+                e => e,
+            };
+            if let Expr::Promise(_) = expr {
+                if let Expr::Promise(p) = std::mem::replace(expr, atom_nil!()) {
+                    pending.push(p);
+                }
+            }
+        }
+    }
+}
+
+pub fn make(state: PromiseState) -> Expr {
+    Expr::Promise(Rc::new(RefCell::new(Promise {
+        state: Rc::new(RefCell::new(state)),
+    })))
+}
+
+/// A promise whose value comes from calling `f` with `args`, which are
+/// quoted so they aren't evaluated again. `f` has to return a promise.
+pub fn lazy_call(f: NativeFn, args: Vec<Expr>, env: &Env) -> Expr {
+    let mut call: List = args.into_iter().map(|a| Expr::Quote(Box::new(a))).collect();
+    call.push_front(Expr::Atom(Box::new(Atom::Native(NativeEnc(f)))));
+    call.push_back(atom_nil!());
+    make(PromiseState::Lazy(Expr::List(Rc::new(call)), env.clone()))
+}
+
+/// Forces `promise`. A chain of delay-force promises is followed in a
+/// loop, each one sharing the box of the first, so it runs in constant
+/// space.
+pub fn force(promise: &Rc<RefCell<Promise>>) -> Result<Expr, EvalError> {
+    loop {
+        let state = promise.borrow().state.clone();
+        let (expr, env, lazy) = match &*state.borrow() {
+            PromiseState::Done(val) => return Ok(val.clone()),
+            PromiseState::Delayed(expr, env) => (expr.clone(), env.clone(), false),
+            PromiseState::Lazy(expr, env) => (expr.clone(), env.clone(), true),
+        };
+        let val = eval_expr(expr, &env)?;
+
+        // Forcing it may have forced the promise itself already,
+        // in which case the first value is the one kept
+        let state = promise.borrow().state.clone();
+        if let PromiseState::Done(val) = &*state.borrow() {
+            return Ok(val.clone());
+        }
+        if !lazy {
+            *state.borrow_mut() = PromiseState::Done(val.clone());
+            return Ok(val);
+        }
+        let next = match val {
+            Expr::Promise(next) => next,
+            e => return Err(EvalError::ExprTypeMismatch("promise".to_string(), e)),
+        };
+        // The box takes over where the other promise is at, and that
+        // promise is pointed at the box, as R7RS's promise-update! does
+        let taken = next.borrow().state.borrow().clone();
+        *state.borrow_mut() = taken;
+        next.borrow_mut().state = state;
+    }
+}
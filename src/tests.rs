@@ -1,16 +1,1562 @@
 use crate::atom_num;
+use crate::atom_bool;
+use crate::display::Displayed;
 use crate::env::Env;
-use crate::evaluator::eval_expr;
-use crate::parser::{parse, Atom, Expr};
-use crate::tokenizer::tokenize;
+use crate::evaluator::error::EvalError;
+use crate::evaluator::{eval_expr, eval_form};
+use crate::parser::error::ParserErr;
+use crate::parser::{parse, parse_all, parse_program, Atom, Expr};
+use crate::tokenizer::error::TokenizeErr;
+use crate::tokenizer::{tokenize, Position};
 use crate::utils::init_map;
+use crate::Error;
 use std::collections::HashMap;
 
+fn global_env() -> Env {
+    let mut map: HashMap<String, Expr> = HashMap::default();
+    init_map(&mut map);
+    Env::from(map)
+}
+
+fn run(src: &str, env: &Env) -> Expr {
+    eval_expr(parse(&mut tokenize(src).unwrap()).unwrap(), env).unwrap()
+}
+
+// Evaluates every form in `src`, returning the value of the last one
+fn run_all(src: &str, env: &Env) -> Expr {
+    let forms = parse_all(&mut tokenize(src).unwrap()).unwrap();
+    forms
+        .into_iter()
+        .map(|f| eval_expr(f, env).unwrap())
+        .last()
+        .unwrap()
+}
+
+// Like run_all, but expands each form first as the interpreter does
+fn run_forms(src: &str, env: &Env) -> Expr {
+    let forms = parse_all(&mut tokenize(src).unwrap()).unwrap();
+    forms
+        .into_iter()
+        .map(|f| eval_form(f, env).unwrap())
+        .last()
+        .unwrap()
+}
+
 #[test]
 fn test() {
     let mut map: HashMap<String, Expr> = HashMap::default();
     init_map(&mut map);
     let env = Env::from(map);
-    let res = eval_expr(parse(&mut tokenize("(+ 1 2)")).unwrap(), &env).unwrap();
+    let res = eval_expr(parse(&mut tokenize("(+ 1 2)").unwrap()).unwrap(), &env).unwrap();
     assert_eq!(res, atom_num!(3));
 }
+
+#[test]
+fn tail_calls_run_in_constant_stack() {
+    let mut map: HashMap<String, Expr> = HashMap::default();
+    init_map(&mut map);
+    let env = Env::from(map);
+    let define = "(define walk (lambda (n) (if (= n 0) (quote done) (walk (- n 1)))))";
+    eval_expr(parse(&mut tokenize(define).unwrap()).unwrap(), &env).unwrap();
+
+    // Deep enough to overflow the stack if every call took a Rust frame
+    let call = "(walk 100000)";
+    let res = eval_expr(parse(&mut tokenize(call).unwrap()).unwrap(), &env).unwrap();
+    assert_eq!(res, Expr::Atom(Box::new(Atom::Symbol("done".to_string()))));
+}
+
+#[test]
+fn lambda_calls_get_fresh_frames() {
+    let env = global_env();
+    run("(define make-adder (lambda (n) (lambda (x) (+ x n))))", &env);
+    run("(define add2 (make-adder 2))", &env);
+    run("(define add5 (make-adder 5))", &env);
+    assert_eq!(run("(add2 1)", &env), atom_num!(3));
+    assert_eq!(run("(add5 1)", &env), atom_num!(6));
+
+    run("(define counter ((lambda (c) (lambda () (set! c (+ c 1)))) 0))", &env);
+    run("(counter)", &env);
+    assert_eq!(run("(counter)", &env), atom_num!(2));
+}
+
+#[test]
+fn parse_all_returns_every_form() {
+    let env = global_env();
+    let exprs = parse_all(&mut tokenize("(define a 2) ; comment\n(+ a 1) a").unwrap()).unwrap();
+    assert_eq!(exprs.len(), 3);
+
+    let results: Vec<Expr> = exprs
+        .into_iter()
+        .map(|e| eval_expr(e, &env).unwrap())
+        .collect();
+    assert_eq!(results, vec![atom_num!(2), atom_num!(3), atom_num!(2)]);
+}
+
+#[test]
+fn forms_and_errors_carry_positions() {
+    let forms = parse_program(&mut tokenize("(+ 1 2)\n  (car\n  'a)").unwrap()).unwrap();
+    assert_eq!(forms[1].span.start, Position { line: 2, column: 3 });
+    assert_eq!(forms[1].span.end, Position { line: 3, column: 6 });
+
+    let err = parse_program(&mut tokenize("(a\n  . b c)").unwrap()).unwrap_err();
+    assert_eq!(err.span().unwrap().start, Position { line: 2, column: 7 });
+    assert_eq!(
+        err.report("(a\n  . b c)").lines().last(),
+        Some("  |       ^")
+    );
+}
+
+fn report_eval_error(src: &str) -> String {
+    let env = global_env();
+    let forms = parse_program(&mut tokenize(src).unwrap()).unwrap();
+    for form in forms {
+        if let Err(e) = eval_expr(form.expr, &env) {
+            return e.report(src, &form.node);
+        }
+    }
+    panic!("no error evaluating {}", src);
+}
+
+#[test]
+fn eval_errors_point_at_the_failing_call() {
+    let src = "(define p (make-parameter 0))\n(parameterize ((p 1)) (car '()))";
+    assert_eq!(
+        report_eval_error(src).lines().last(),
+        Some("  |                       ^^^^^^^^^")
+    );
+
+    let src = "(define (f x) (car x))\n(list (car '(1)) (f '()))";
+    assert_eq!(
+        report_eval_error(src).lines().last(),
+        Some("  |                  ^^^^^^^")
+    );
+
+    let src = "(if #t (+ 1 (* 2 undefined)))";
+    assert_eq!(
+        report_eval_error(src).lines().last(),
+        Some("  |                  ^^^^^^^^^")
+    );
+
+    let src = "(guard (e (#t 0)) (car '()))\n(+ 1 (string-ref \"a\" 3))";
+    assert_eq!(
+        report_eval_error(src).lines().last(),
+        Some("  |      ^^^^^^^^^^^^^^^^^^")
+    );
+
+    // An error escaped from by its handler is forgotten
+    let src = "(call/cc (lambda (k) (with-exception-handler (lambda (e) (k 1)) (lambda () (car 5)))))
+(car (car (list 5)))";
+    assert_eq!(
+        report_eval_error(src).lines().last(),
+        Some("  | ^^^^^^^^^^^^^^^^^^^^")
+    );
+}
+
+fn eval_source(src: &str, env: &Env) -> Result<Expr, Error> {
+    let mut tokens = tokenize(src)?;
+    Ok(eval_expr(parse(&mut tokens)?, env)?)
+}
+
+#[test]
+fn pipeline_errors_convert_into_crate_error() {
+    let env = global_env();
+    assert!(matches!(
+        eval_source("\"abc", &env),
+        Err(Error::Tokenize(_))
+    ));
+    assert!(matches!(
+        eval_source("(+ 1 2))", &env),
+        Err(Error::Parse(_))
+    ));
+
+    let err = eval_source("(car 5)", &env).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Eval error: Type mismatch, expected list but found `5'"
+    );
+}
+
+#[test]
+fn bad_arguments_return_errors_instead_of_panicking() {
+    let env = global_env();
+    assert!(matches!(
+        eval_source("(+ 1 '(1))", &env),
+        Err(Error::Eval(EvalError::ExprTypeMismatch(..)))
+    ));
+    assert!(matches!(
+        eval_source("(/ 5 0)", &env),
+        Err(Error::Eval(EvalError::DivisionByZero))
+    ));
+    assert!(matches!(
+        eval_source("(/ 0)", &env),
+        Err(Error::Eval(EvalError::DivisionByZero))
+    ));
+    assert!(matches!(
+        eval_source("(/ 1 0)", &env),
+        Err(Error::Eval(EvalError::DivisionByZero))
+    ));
+}
+
+#[test]
+fn malformed_forms_return_errors_instead_of_panicking() {
+    let env = global_env();
+    assert!(matches!(
+        eval_source("(define (1) 2)", &env),
+        Err(Error::Eval(EvalError::TypeMismatch(..)))
+    ));
+    assert!(matches!(
+        eval_source("(lambda ((x)) 1)", &env),
+        Err(Error::Eval(EvalError::ExprTypeMismatch(..)))
+    ));
+    assert!(matches!(
+        eval_source(",a", &env),
+        Err(Error::Eval(EvalError::BadSyntax(_)))
+    ));
+    assert!(matches!(
+        eval_source("(5 1)", &env),
+        Err(Error::Eval(EvalError::NotCallable(_)))
+    ));
+    assert!(matches!(
+        eval_source("(if)", &env),
+        Err(Error::Eval(EvalError::WrongNumOfArgs(3, 0)))
+    ));
+}
+
+#[test]
+fn integers_promote_to_bignums_on_overflow() {
+    let env = global_env();
+    run(
+        "(define fact (lambda (n acc) (if (car n) (fact (cdr n) (* (car n) acc)) acc)))",
+        &env,
+    );
+    let res = run(
+        "(fact '(1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 #f) 1)",
+        &env,
+    );
+    assert_eq!(res.to_string(), "15511210043330985984000000");
+
+    let max = run("(- (* 9223372036854775807 2) 9223372036854775807)", &env);
+    assert_eq!(max, atom_num!(i64::MAX));
+    assert_eq!(
+        run("(- -9223372036854775808)", &env).to_string(),
+        "9223372036854775808"
+    );
+}
+
+#[test]
+fn numeric_literals_take_radix_and_exactness_prefixes() {
+    let env = global_env();
+    assert_eq!(run("#xFF", &env).to_string(), "255");
+    assert_eq!(run("#b-101", &env).to_string(), "-5");
+    assert_eq!(run("#e1.1", &env).to_string(), "11/10");
+    assert_eq!(run("#i1/4", &env).to_string(), "0.25");
+    assert_eq!(run("1e3", &env).to_string(), "1000.0");
+    assert_eq!(run("-inf.0", &env).to_string(), "-inf.0");
+    assert_eq!(run("3.5", &env), atom_num!(3.5));
+}
+
+#[test]
+fn exact_division_makes_rationals() {
+    let env = global_env();
+    assert_eq!(run("(/ 6 4)", &env).to_string(), "3/2");
+    assert_eq!(run("(+ 1/3 2/3)", &env).to_string(), "1");
+}
+
+#[test]
+fn inexact_operands_make_the_result_inexact() {
+    let env = global_env();
+    assert_eq!(run("(+ 1/2 0.5)", &env).to_string(), "1.0");
+    assert_eq!(run("(* 2 0.25)", &env).to_string(), "0.5");
+    assert_eq!(run("(/ 1 0.0)", &env).to_string(), "+inf.0");
+}
+
+#[test]
+fn numeric_comparisons_chain_over_every_argument() {
+    let env = global_env();
+    assert_eq!(run("(= 1 1.0 2/2)", &env), atom_bool!(true));
+    assert_eq!(run("(< 1 3/2 2.0 3)", &env), atom_bool!(true));
+    assert_eq!(run("(>= 3 3 2)", &env), atom_bool!(true));
+    assert_eq!(run("(< 1 +nan.0)", &env), atom_bool!(false));
+}
+
+#[test]
+fn eqv_compares_symbols_and_numbers_of_the_same_exactness() {
+    let env = global_env();
+    assert_eq!(run("(eqv? 'a 'a)", &env), atom_bool!(true));
+    assert_eq!(run("(eqv? 2 2.0)", &env), atom_bool!(false));
+}
+
+#[test]
+fn eq_compares_procedures_by_identity() {
+    let env = global_env();
+    assert_eq!(run("(eq? car car)", &env), atom_bool!(true));
+    assert_eq!(run("(eq? car cdr)", &env), atom_bool!(false));
+
+    run("(define f (lambda (x) x))", &env);
+    run("(define g (lambda (x) x))", &env);
+    assert_eq!(run("(eq? f f)", &env), atom_bool!(true));
+    assert_eq!(run("(eqv? f g)", &env), atom_bool!(false));
+}
+
+#[test]
+fn equal_compares_lists_by_their_contents() {
+    let env = global_env();
+    assert_eq!(
+        run("(equal? '(1 (2 \"x\")) '(1 (2 \"x\")))", &env),
+        atom_bool!(true)
+    );
+    assert_eq!(run("(equal? '(1 2) '(1 2.0))", &env), atom_bool!(false));
+}
+
+#[test]
+fn not_is_true_only_for_false() {
+    let env = global_env();
+    assert_eq!(run("(not #f)", &env), atom_bool!(true));
+    assert_eq!(run("(not '())", &env), atom_bool!(false));
+}
+
+#[test]
+fn builtins_are_first_class_values() {
+    let env = global_env();
+    assert_eq!(run("((lambda (op) (op 6 3)) /)", &env), atom_num!(2));
+    assert_eq!(run("(procedure? car)", &env), atom_bool!(true));
+    assert_eq!(run("(procedure? 'car)", &env), atom_bool!(false));
+    assert_eq!(run("(procedure? if)", &env), atom_bool!(false));
+    assert!(matches!(
+        eval_source("(5 1)", &env),
+        Err(Error::Eval(EvalError::NotCallable(_)))
+    ));
+}
+
+#[test]
+fn apply_and_map_spread_arguments() {
+    let env = global_env();
+    assert_eq!(run("(apply + 1 2 '(3 4))", &env), atom_num!(10));
+    assert_eq!(run("(map car '((1 2) (3 4)))", &env).to_string(), "(1 3)");
+    assert_eq!(
+        run("(map + '(1 2 3) '(10 20))", &env).to_string(),
+        "(11 22)"
+    );
+    assert_eq!(
+        run("(map (lambda (x) (* x x)) (list 1 2 3))", &env).to_string(),
+        "(1 4 9)"
+    );
+    assert!(matches!(
+        eval_source("(apply + 1)", &env),
+        Err(Error::Eval(EvalError::ExprTypeMismatch(..)))
+    ));
+    assert!(matches!(
+        eval_source("(map car 5)", &env),
+        Err(Error::Eval(EvalError::ExprTypeMismatch(..)))
+    ));
+}
+
+#[test]
+fn cdr_returns_the_tail_of_pairs() {
+    let env = global_env();
+    assert_eq!(run("(cdr '(1 . 2))", &env), atom_num!(2));
+    assert_eq!(run("(cdr '(1))", &env).to_string(), "()");
+}
+
+#[test]
+fn eval_evaluates_data_as_code() {
+    let env = global_env();
+    assert_eq!(run("(eval '(+ 1 2))", &env), atom_num!(3));
+}
+
+#[test]
+fn quasiquote_unquotes_and_splices() {
+    let env = global_env();
+    assert_eq!(
+        run("`(1 ,(+ 1 1) ,@(list 3 4) 5)", &env).to_string(),
+        "(1 2 3 4 5)"
+    );
+    assert_eq!(run("`(,@(list))", &env).to_string(), "()");
+    assert_eq!(
+        run("(quasiquote (1 (unquote (+ 1 2))))", &env).to_string(),
+        "(1 3)"
+    );
+}
+
+#[test]
+fn quasiquote_fills_dotted_tails() {
+    let env = global_env();
+    assert_eq!(run("`(1 . ,(+ 1 1))", &env).to_string(), "(1 . 2)");
+    assert_eq!(run("`(1 . ,(list 2 3))", &env).to_string(), "(1 2 3)");
+    assert_eq!(run("'(a . (b c))", &env).to_string(), "(a b c)");
+}
+
+#[test]
+fn nested_quasiquotes_unquote_at_their_own_level() {
+    let env = global_env();
+    assert_eq!(
+        run("`(a `(b ,(c ,(+ 1 2))))", &env).to_string(),
+        "(a `(b ,(c 3)))"
+    );
+}
+
+#[test]
+fn unquote_outside_quasiquote_is_an_error() {
+    let env = global_env();
+    assert!(matches!(
+        eval_source(",x", &env),
+        Err(Error::Eval(EvalError::BadSyntax(_)))
+    ));
+    assert!(matches!(
+        eval_source("`,@(list 1)", &env),
+        Err(Error::Eval(EvalError::BadSyntax(_)))
+    ));
+}
+
+#[test]
+fn let_binds_in_parallel() {
+    let env = global_env();
+    assert_eq!(run("(let ((x 1) (y 2)) (+ x y))", &env), atom_num!(3));
+    assert_eq!(
+        run("(let ((x 1)) (let ((x 2) (y x)) y))", &env),
+        atom_num!(1)
+    );
+    assert_eq!(run("(let () 5)", &env), atom_num!(5));
+
+    // nothing leaks out of the let
+    run("(let ((z 1)) z)", &env);
+    assert!(matches!(
+        eval_source("z", &env),
+        Err(Error::Eval(EvalError::UndefinedSymbol(_)))
+    ));
+}
+
+#[test]
+fn let_star_and_letrec_star_bind_in_sequence() {
+    let env = global_env();
+    assert_eq!(run("(let* ((x 1) (x (+ x 1))) x)", &env), atom_num!(2));
+    assert_eq!(run("(letrec* ((a 1) (b (+ a 1))) b)", &env), atom_num!(2));
+}
+
+#[test]
+fn letrec_binds_mutually_recursive_procedures() {
+    let env = global_env();
+    let src = "(letrec ((ev? (lambda (n) (if (= n 0) #t (od? (- n 1)))))
+                        (od? (lambda (n) (if (= n 0) #f (ev? (- n 1))))))
+                 (ev? 100))";
+    assert_eq!(run(src, &env), atom_bool!(true));
+
+    // letrec inits can't see each other's values yet
+    assert!(matches!(
+        eval_source("(letrec ((a 1) (b (+ a 1))) b)", &env),
+        Err(Error::Eval(EvalError::UndefinedSymbol(_)))
+    ));
+}
+
+#[test]
+fn named_let_loops_in_constant_stack() {
+    let env = global_env();
+    let src = "(let loop ((i 0) (acc 0))
+                 (if (= i 100000) acc (loop (+ i 1) (+ acc i))))";
+    assert_eq!(run(src, &env), atom_num!(4999950000i64));
+}
+
+#[test]
+fn begin_and_or_short_circuit() {
+    let env = global_env();
+    assert_eq!(run("(begin 1 2 3)", &env), atom_num!(3));
+    assert_eq!(run("(and)", &env), atom_bool!(true));
+    assert_eq!(run("(and 1 #f undefined)", &env), atom_bool!(false));
+    assert_eq!(run("(and 1 2 3)", &env), atom_num!(3));
+    assert_eq!(run("(or)", &env), atom_bool!(false));
+    assert_eq!(run("(or #f 2 undefined)", &env), atom_num!(2));
+}
+
+#[test]
+fn when_and_unless_run_their_body_on_a_condition() {
+    let env = global_env();
+    assert_eq!(run("(when (> 2 1) 1 2)", &env), atom_num!(2));
+    assert_eq!(run("(unless (> 2 1) 1 2)", &env).to_string(), "()");
+}
+
+#[test]
+fn cond_picks_the_first_true_clause() {
+    let env = global_env();
+    assert_eq!(
+        run("(cond (#f 1) ((+ 1 1) => (lambda (x) (* x 10))))", &env),
+        atom_num!(20)
+    );
+    assert_eq!(run("(cond (#f 1) (else 2 3))", &env), atom_num!(3));
+    assert_eq!(run("(cond (5))", &env), atom_num!(5));
+    assert_eq!(run("(cond (#f 1))", &env).to_string(), "()");
+    assert!(matches!(
+        eval_source("(cond (else 1) (#t 2))", &env),
+        Err(Error::Eval(EvalError::BadSyntax(_)))
+    ));
+}
+
+#[test]
+fn case_compares_the_key_with_eqv() {
+    let env = global_env();
+    let src = "(case (* 2 3) ((2 3 5 7) 'prime) ((1 4 6 8 9) 'composite))";
+    assert_eq!(run(src, &env).to_string(), "composite");
+    assert_eq!(
+        run("(case 'x ((a) 1) (else => (lambda (k) k)))", &env).to_string(),
+        "x"
+    );
+}
+
+#[test]
+fn conditionals_leave_their_last_expression_in_tail_position() {
+    let env = global_env();
+    let src = "(let loop ((i 0))
+                 (cond ((= i 100000) i)
+                       (else (and #t (or #f (when #t (loop (+ i 1))))))))";
+    assert_eq!(run(src, &env), atom_num!(100000));
+}
+
+#[test]
+fn define_shorthand_names_the_procedure() {
+    let env = global_env();
+    let fact = "(define (fact n) (if (= n 0) 1 (* n (fact (- n 1)))))";
+    assert_eq!(run(fact, &env).to_string(), "<lambda fact>");
+    assert_eq!(run("(fact 5)", &env), atom_num!(120));
+    assert_eq!(
+        run("(define (seven) 7)", &env).to_string(),
+        "<lambda seven>"
+    );
+    assert_eq!(run("(seven)", &env), atom_num!(7));
+    assert_eq!(
+        run("(let loop ((i 0)) loop)", &env).to_string(),
+        "<lambda loop>"
+    );
+
+    let err = eval_source("(fact 1 2)", &env).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Eval error: Wrong number of arguments to `<lambda fact>', expected 1 but found 2"
+    );
+}
+
+#[test]
+fn curried_define_nests_lambdas() {
+    let env = global_env();
+    assert_eq!(
+        run("(define ((adder n) x) (+ n x))", &env).to_string(),
+        "<lambda adder>"
+    );
+    assert_eq!(run("((adder 3) 4)", &env), atom_num!(7));
+    assert_eq!(run("(adder 3)", &env).to_string(), "<lambda>");
+}
+
+#[test]
+fn internal_defines_stay_in_the_body() {
+    let env = global_env();
+    run(
+        "(define (f x) (define a 10) (define (b) (* a x)) (b))",
+        &env,
+    );
+    assert_eq!(run("(f 2)", &env), atom_num!(20));
+    assert!(matches!(
+        eval_source("a", &env),
+        Err(Error::Eval(EvalError::UndefinedSymbol(_)))
+    ));
+}
+
+#[test]
+fn rest_arguments_collect_the_remaining_ones() {
+    let env = global_env();
+    assert_eq!(run("((lambda x x) 1 2 3)", &env).to_string(), "(1 2 3)");
+    assert_eq!(run("((lambda x x))", &env).to_string(), "()");
+    assert_eq!(
+        run("((lambda (a . r) (list a r)) 1 2 3)", &env).to_string(),
+        "(1 (2 3))"
+    );
+    run("(define (f a . r) r)", &env);
+    assert_eq!(run("(f 1)", &env).to_string(), "()");
+
+    let err = eval_source("(f)", &env).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Eval error: Wrong number of arguments to `<lambda f>', expected at least 1 but found 0"
+    );
+}
+
+#[test]
+fn optional_arguments_take_their_defaults() {
+    let env = global_env();
+    assert_eq!(
+        run(
+            "((lambda (a #!optional (b (* a 2)) c) (list a b c)) 1)",
+            &env
+        )
+        .to_string(),
+        "(1 2 ())"
+    );
+    assert_eq!(
+        run(
+            "((lambda (a #!optional b #!rest r) (list a b r)) 1 2 3 4)",
+            &env
+        )
+        .to_string(),
+        "(1 2 (3 4))"
+    );
+    assert!(matches!(
+        eval_source("(lambda (a #!rest) 1)", &env),
+        Err(Error::Eval(EvalError::BadSyntax(_)))
+    ));
+}
+
+#[test]
+fn case_lambda_dispatches_on_the_number_of_arguments() {
+    let env = global_env();
+    let g = "(define g (case-lambda ((x) (list 1 x)) ((x y) (list 2 x y)) ((x . r) r)))";
+    assert_eq!(run(g, &env).to_string(), "<case-lambda>");
+    assert_eq!(run("(g 1)", &env).to_string(), "(1 1)");
+    assert_eq!(run("(g 1 2)", &env).to_string(), "(2 1 2)");
+    assert_eq!(run("(g 1 2 3)", &env).to_string(), "(2 3)");
+    assert_eq!(run("(procedure? g)", &env), atom_bool!(true));
+
+    let err = eval_source("((case-lambda ((x) 1) ((x y) 2)))", &env).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Eval error: Wrong number of arguments to `<case-lambda>', expected 1 or 2 but found 0"
+    );
+}
+
+#[test]
+fn syntax_rules_binders_do_not_capture_user_variables() {
+    let env = global_env();
+    run(
+        "(define-syntax swap!
+           (syntax-rules () ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))",
+        &env,
+    );
+    run(
+        "(define-syntax my-or
+           (syntax-rules ()
+             ((_) #f)
+             ((_ e) e)
+             ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))",
+        &env,
+    );
+    assert_eq!(
+        run_all(
+            "(define tmp 1) (define y 2) (swap! tmp y) (list tmp y)",
+            &env
+        )
+        .to_string(),
+        "(2 1)"
+    );
+    assert_eq!(run_all("(define t 5) (my-or #f t)", &env), atom_num!(5));
+    assert!(matches!(
+        eval_source("(swap! 1)", &env),
+        Err(Error::Eval(EvalError::BadSyntax(_)))
+    ));
+}
+
+#[test]
+fn syntax_rules_literals_must_match_themselves() {
+    let env = global_env();
+    run(
+        "(define-syntax for
+           (syntax-rules (in) ((_ x in lst body ...) (map (lambda (x) body ...) lst))))",
+        &env,
+    );
+    assert_eq!(
+        run("(for x in '(1 2 3) (* x x))", &env).to_string(),
+        "(1 4 9)"
+    );
+    assert!(matches!(
+        eval_source("(for x on '(1 2 3) x)", &env),
+        Err(Error::Eval(EvalError::BadSyntax(_)))
+    ));
+}
+
+#[test]
+fn syntax_rules_ellipses_nest_and_escape() {
+    let env = global_env();
+    let nest = "(define-syntax nest (syntax-rules () ((_ (a b ...) ...) '((a ...) (b ... ...)))))
+                (nest (1 2 3) (4 5 6))";
+    assert_eq!(run_all(nest, &env).to_string(), "((1 4) (2 3 5 6))");
+    let mid = "(define-syntax mid (syntax-rules () ((_ a ... z . r) '(z a ... r))))
+               (mid 1 2 3 4)";
+    assert_eq!(run_all(mid, &env).to_string(), "(4 1 2 3 ())");
+    let esc = "(define-syntax esc (syntax-rules () ((_ a) '(a (... ...)))))
+               (esc 1)";
+    assert_eq!(run_all(esc, &env).to_string(), "(1 ...)");
+    let custom =
+        "(define-syntax my-if (syntax-rules ::: () ((_ c t e :::) (cond (c t) (else e :::)))))
+                  (my-if #f 1 2 3)";
+    assert_eq!(run_all(custom, &env), atom_num!(3));
+}
+
+#[test]
+fn let_syntax_macros_are_local() {
+    let env = global_env();
+    assert_eq!(
+        run(
+            "(let-syntax ((dbl (syntax-rules () ((_ x) (* x 2))))) (dbl 21))",
+            &env
+        ),
+        atom_num!(42)
+    );
+    assert!(matches!(
+        eval_source("dbl", &env),
+        Err(Error::Eval(EvalError::UndefinedSymbol(_)))
+    ));
+}
+
+#[test]
+fn recursive_macro_uses_run_in_tail_position() {
+    let env = global_env();
+    run_all(
+        "(define-syntax my-or
+           (syntax-rules ()
+             ((_) #f)
+             ((_ e) e)
+             ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))
+         (define (count n) (my-or #f (if (= n 0) 'done (count (- n 1)))))",
+        &env,
+    );
+    assert_eq!(run("(count 20000)", &env).to_string(), "done");
+}
+
+#[test]
+fn define_macro_transforms_unevaluated_forms() {
+    let env = global_env();
+    run(
+        "(define-macro (my-unless c . body) `(if ,c () (begin ,@body)))",
+        &env,
+    );
+    assert_eq!(run("(my-unless #f 1 2)", &env), atom_num!(2));
+    assert!(matches!(
+        eval_source("(define-macro m 5)", &env),
+        Err(Error::Eval(EvalError::ExprTypeMismatch(..)))
+    ));
+}
+
+#[test]
+fn gensym_avoids_captures_in_define_macro() {
+    let env = global_env();
+    run(
+        "(define-macro (swap! a b)
+           (let ((tmp (gensym)))
+             `(let ((,tmp ,a)) (set! ,a ,b) (set! ,b ,tmp))))",
+        &env,
+    );
+    assert_eq!(
+        run_all("(define x 1) (define y 2) (swap! x y) (list x y)", &env).to_string(),
+        "(2 1)"
+    );
+    assert_eq!(run("(eq? (gensym) (gensym))", &env), atom_bool!(false));
+}
+
+#[test]
+fn macroexpand_shows_expansions() {
+    let env = global_env();
+    run(
+        "(define-macro (my-unless c . body) `(if ,c () (begin ,@body)))",
+        &env,
+    );
+    run(
+        "(define-macro my-when (lambda (c . body) `(my-unless (not ,c) ,@body)))",
+        &env,
+    );
+    assert_eq!(
+        run("(macroexpand-1 '(my-when #t 1))", &env).to_string(),
+        "(my-unless (not #t) 1)"
+    );
+    assert_eq!(
+        run("(macroexpand '(my-when #t 1))", &env).to_string(),
+        "(if (not #t) () (begin 1))"
+    );
+    assert_eq!(run("(macroexpand '(+ 1 2))", &env).to_string(), "(+ 1 2)");
+}
+
+#[test]
+fn call_cc_escapes_with_a_value() {
+    let env = global_env();
+    assert_eq!(
+        run("(+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))", &env),
+        atom_num!(3)
+    );
+    assert_eq!(run("(call/cc (lambda (k) 5))", &env), atom_num!(5));
+    assert_eq!(
+        run(
+            "(call/cc (lambda (outer) (call/cc (lambda (inner) (outer 1))) 2))",
+            &env
+        ),
+        atom_num!(1)
+    );
+    assert!(matches!(
+        eval_source("(call/cc 1)", &env),
+        Err(Error::Eval(EvalError::NotCallable(_)))
+    ));
+}
+
+#[test]
+fn continuations_return_early_from_loops() {
+    let env = global_env();
+    run(
+        "(define (find-first p l)
+           (call/cc (lambda (return)
+             (for-each (lambda (x) (if (p x) (return x))) l)
+             #f)))",
+        &env,
+    );
+    assert_eq!(
+        run("(find-first (lambda (x) (> x 2)) '(1 2 3 4))", &env),
+        atom_num!(3)
+    );
+}
+
+#[test]
+fn continuations_expire_once_call_cc_returns() {
+    let env = global_env();
+    run_all(
+        "(define saved ()) (call/cc (lambda (k) (set! saved k)))",
+        &env,
+    );
+    assert!(matches!(
+        eval_source("(saved 5)", &env),
+        Err(Error::Eval(EvalError::ContinuationExpired))
+    ));
+}
+
+#[test]
+fn dynamic_wind_runs_after_when_escaping() {
+    let env = global_env();
+    run_all(
+        "(define trace ())
+         (define (note x) (set! trace (cons x trace)))",
+        &env,
+    );
+    let src = "(call/cc (lambda (k)
+                 (dynamic-wind (lambda () (note 'in))
+                               (lambda () (k 'out) (note 'never))
+                               (lambda () (note 'after)))))";
+    assert_eq!(run(src, &env).to_string(), "out");
+    assert_eq!(run("trace", &env).to_string(), "(after in)");
+    assert_eq!(
+        run(
+            "(dynamic-wind (lambda () 1) (lambda () 2) (lambda () 3))",
+            &env
+        ),
+        atom_num!(2)
+    );
+}
+
+#[test]
+fn guard_catches_raised_objects_and_errors() {
+    let env = global_env();
+    assert_eq!(
+        run("(guard (e (#t (list 'caught e))) (raise 'boom))", &env).to_string(),
+        "(caught boom)"
+    );
+    assert_eq!(
+        run(
+            "(guard (e ((error-object? e) (error-object-irritants e))) (error \"bad\" 1 2))",
+            &env
+        )
+        .to_string(),
+        "(1 2)"
+    );
+    assert_eq!(
+        run(
+            "(guard (e ((error-object? e) (error-object-message e))) (car 5))",
+            &env
+        )
+        .to_string(),
+        "\"Type mismatch, expected list but found `5'\""
+    );
+    assert_eq!(
+        run("(guard (e ((eq? e 'x) => not)) (raise 'x))", &env),
+        atom_bool!(false)
+    );
+    assert_eq!(run("(guard (e (#t 'never)) (+ 1 1))", &env), atom_num!(2));
+}
+
+#[test]
+fn guard_reraises_what_no_clause_takes() {
+    let env = global_env();
+    assert_eq!(
+        run(
+            "(guard (e (#t (list 'outer e))) (guard (e ((eq? e 'y) 'inner)) (raise 'x)))",
+            &env
+        )
+        .to_string(),
+        "(outer x)"
+    );
+    assert!(matches!(
+        eval_source("(raise 'oops)", &env),
+        Err(Error::Eval(EvalError::Raise(_)))
+    ));
+    // Uncaught built-in errors keep their own kind
+    assert!(matches!(
+        eval_source("(guard (e (#f 1)) (car 5))", &env),
+        Err(Error::Eval(EvalError::ExprTypeMismatch(..)))
+    ));
+}
+
+#[test]
+fn exception_handlers_see_raised_objects() {
+    let env = global_env();
+    assert_eq!(
+        run(
+            "(with-exception-handler (lambda (e) 10) (lambda () (+ 1 (raise-continuable 'oops))))",
+            &env
+        ),
+        atom_num!(11)
+    );
+    let src = "(call/cc (lambda (k)
+                 (with-exception-handler (lambda (e) (k (list 'handled e)))
+                                         (lambda () (raise 'x)))))";
+    assert_eq!(run(src, &env).to_string(), "(handled x)");
+    let src = "(call/cc (lambda (k)
+                 (with-exception-handler (lambda (e) (k (error-object? e)))
+                                         (lambda () (car 5)))))";
+    assert_eq!(run(src, &env), atom_bool!(true));
+    let src = "(with-exception-handler (lambda (e) 'outer)
+                 (lambda () (guard (e (#t (list 'inner e))) (raise 'x))))";
+    assert_eq!(run(src, &env).to_string(), "(inner x)");
+
+    // A handler can't return from a non-continuable raise
+    assert!(matches!(
+        eval_source(
+            "(with-exception-handler (lambda (e) 1) (lambda () (raise 'x)))",
+            &env
+        ),
+        Err(Error::Eval(EvalError::Raise(_)))
+    ));
+}
+
+#[test]
+fn exception_handlers_run_where_errors_happen() {
+    let env = global_env();
+    run("(define p (make-parameter 1))", &env);
+    let src = "(call/cc (lambda (k)
+                 (with-exception-handler (lambda (e) (k (p)))
+                                         (lambda () (parameterize ((p 2)) (car 5))))))";
+    assert_eq!(run(src, &env), atom_num!(2));
+    let src = "(call/cc (lambda (k)
+                 (with-exception-handler (lambda (e) (k (p)))
+                                         (lambda () (parameterize ((p 3)) undefined-var)))))";
+    assert_eq!(run(src, &env), atom_num!(3));
+
+    // The handler runs before the thunk is left
+    run("(define seen '())", &env);
+    let src = "(call/cc (lambda (k)
+                 (with-exception-handler
+                   (lambda (e) (set! seen (cons 'handler seen)) (k #f))
+                   (lambda ()
+                     (dynamic-wind (lambda () #f)
+                                   (lambda () (car 5))
+                                   (lambda () (set! seen (cons 'after seen))))))))";
+    run(src, &env);
+    assert_eq!(run("seen", &env).to_string(), "(after handler)");
+}
+
+#[test]
+fn values_are_passed_to_consumers() {
+    let env = global_env();
+    assert_eq!(run("(values 5)", &env), atom_num!(5));
+    assert_eq!(
+        run("(values 1 2)", &env),
+        Expr::Values(Box::new(vec![atom_num!(1), atom_num!(2)]))
+    );
+    assert_eq!(
+        run("(call-with-values (lambda () (values 1 2)) +)", &env),
+        atom_num!(3)
+    );
+    assert_eq!(
+        run("(call-with-values (lambda () 7) list)", &env).to_string(),
+        "(7)"
+    );
+    assert_eq!(
+        run("(call-with-values values list)", &env).to_string(),
+        "()"
+    );
+    assert_eq!(
+        run(
+            "(call-with-values (lambda () (call/cc (lambda (k) (k 1 2)))) list)",
+            &env
+        )
+        .to_string(),
+        "(1 2)"
+    );
+}
+
+#[test]
+fn let_values_binds_in_parallel_and_let_star_values_in_sequence() {
+    let env = global_env();
+    assert_eq!(
+        run(
+            "(let-values (((a b) (values 1 2)) ((c . d) (values 3 4 5))) (list a b c d))",
+            &env
+        )
+        .to_string(),
+        "(1 2 3 (4 5))"
+    );
+    assert_eq!(
+        run(
+            "(let ((a 10)) (let-values (((a) (values 1)) ((b) (values a))) (list a b)))",
+            &env
+        )
+        .to_string(),
+        "(1 10)"
+    );
+    assert_eq!(
+        run(
+            "(let ((a 10)) (let*-values (((a) (values 1)) ((b) (values a))) (list a b)))",
+            &env
+        )
+        .to_string(),
+        "(1 1)"
+    );
+    assert!(matches!(
+        eval_source("(let-values (((a) (values 1 2))) a)", &env),
+        Err(Error::Eval(EvalError::ProcedureArity(..)))
+    ));
+}
+
+#[test]
+fn define_values_and_receive_spread_values_over_formals() {
+    let env = global_env();
+    assert_eq!(
+        run_all("(define-values (q . r) (values 7 8 9)) (list q r)", &env).to_string(),
+        "(7 (8 9))"
+    );
+    assert_eq!(
+        run("(receive (x . rest) (values 1 2 3) (list x rest))", &env).to_string(),
+        "(1 (2 3))"
+    );
+    assert!(matches!(
+        eval_source("(receive (a b) (values 1) a)", &env),
+        Err(Error::Eval(EvalError::ProcedureArity(..)))
+    ));
+}
+
+#[test]
+fn promises_are_forced_once() {
+    let env = global_env();
+    run_all(
+        "(define count 0)
+         (define p (delay (begin (set! count (+ count 1)) count)))",
+        &env,
+    );
+    assert_eq!(
+        run("(list (force p) (force p) count)", &env).to_string(),
+        "(1 1 1)"
+    );
+    // Forcing itself again keeps the first value computed
+    run(
+        "(define r (delay (begin (set! count (+ count 1)) (if (> count 5) count (force r)))))",
+        &env,
+    );
+    assert_eq!(run("(force r)", &env), atom_num!(6));
+}
+
+#[test]
+fn force_passes_other_values_through() {
+    let env = global_env();
+    assert_eq!(run("(force 5)", &env), atom_num!(5));
+    assert_eq!(run("(force (make-promise 7))", &env), atom_num!(7));
+    assert_eq!(run("(promise? (delay 1))", &env), atom_bool!(true));
+    assert!(matches!(
+        eval_source("(force)", &env),
+        Err(Error::Eval(EvalError::WrongNumOfArgs(1, 0)))
+    ));
+}
+
+#[test]
+fn delay_force_chains_run_in_constant_space() {
+    let env = global_env();
+    run(
+        "(define (countdown n) (delay-force (if (= n 0) (delay 'done) (countdown (- n 1)))))",
+        &env,
+    );
+    assert_eq!(run("(force (countdown 100000))", &env).to_string(), "done");
+}
+
+#[test]
+fn streams_are_computed_as_needed() {
+    let env = global_env();
+    run("(define (ints n) (stream-cons n (ints (+ n 1))))", &env);
+    assert_eq!(
+        run("(stream->list 5 (ints 0))", &env).to_string(),
+        "(0 1 2 3 4)"
+    );
+    assert_eq!(
+        run("(stream-car (stream-cdr (ints 0)))", &env),
+        atom_num!(1)
+    );
+    assert_eq!(
+        run(
+            "(stream->list (stream-take 5 (stream-map * (ints 0) (ints 0))))",
+            &env
+        )
+        .to_string(),
+        "(0 1 4 9 16)"
+    );
+    assert_eq!(
+        run(
+            "(stream-car (stream-filter (lambda (x) (> x 50000)) (ints 0)))",
+            &env
+        ),
+        atom_num!(50001)
+    );
+    assert_eq!(
+        run("(stream->list (list->stream '(1 2 3)))", &env).to_string(),
+        "(1 2 3)"
+    );
+    assert_eq!(
+        run(
+            "(list (stream-null? stream-null) (stream-pair? (ints 0)))",
+            &env
+        )
+        .to_string(),
+        "(#t #t)"
+    );
+
+    // The car of a stream is only computed when asked for
+    run("(define s (stream-cons (car 5) stream-null))", &env);
+    assert!(matches!(
+        eval_source("(stream-car s)", &env),
+        Err(Error::Eval(EvalError::ExprTypeMismatch(..)))
+    ));
+}
+
+#[test]
+fn parameters_are_rebound_by_parameterize() {
+    let env = global_env();
+    assert_eq!(
+        run_all("(define radix (make-parameter 10)) (radix)", &env),
+        atom_num!(10)
+    );
+    assert_eq!(
+        run_all(
+            "(define (show) (radix)) (parameterize ((radix 2)) (show))",
+            &env
+        ),
+        atom_num!(2)
+    );
+    assert_eq!(run("(radix)", &env), atom_num!(10));
+    assert_eq!(
+        run("(parameterize ((radix 1) (radix 2)) (radix))", &env),
+        atom_num!(2)
+    );
+    assert_eq!(run("(radix)", &env), atom_num!(10));
+    assert!(matches!(
+        eval_source("(parameterize ((car 1)) 2)", &env),
+        Err(Error::Eval(EvalError::ExprTypeMismatch(..)))
+    ));
+}
+
+#[test]
+fn parameter_converters_see_every_new_value() {
+    let env = global_env();
+    assert_eq!(
+        run_all(
+            "(define p (make-parameter 10 (lambda (x) (* x 2)))) (p)",
+            &env
+        ),
+        atom_num!(20)
+    );
+    assert_eq!(run("(parameterize ((p 3)) (p))", &env), atom_num!(6));
+}
+
+#[test]
+fn parameterize_restores_values_however_it_is_left() {
+    let env = global_env();
+    run("(define radix (make-parameter 10))", &env);
+    assert_eq!(
+        run(
+            "(guard (e (#t (radix))) (parameterize ((radix 16)) (car 5)))",
+            &env
+        ),
+        atom_num!(10)
+    );
+    assert_eq!(
+        run(
+            "(call/cc (lambda (k) (parameterize ((radix 8)) (k (radix)))))",
+            &env
+        ),
+        atom_num!(8)
+    );
+    assert_eq!(run("(radix)", &env), atom_num!(10));
+}
+
+#[test]
+fn characters_read_and_print_as_literals() {
+    let env = global_env();
+    assert_eq!(run("#\\a", &env).to_string(), "#\\a");
+    assert_eq!(
+        run("'(#\\( #\\) #\\space)", &env).to_string(),
+        "(#\\( #\\) #\\space)"
+    );
+    assert_eq!(run("#\\x41", &env).to_string(), "#\\A");
+    assert_eq!(run("(integer->char 10)", &env).to_string(), "#\\newline");
+    assert!(matches!(
+        eval_source("#\\bogus", &env),
+        Err(Error::Parse(ParserErr::UnknownChar(_)))
+    ));
+}
+
+#[test]
+fn display_writes_strings_and_chars_as_their_contents() {
+    let env = global_env();
+    let mixed = run("'(\"a\" #\\b #\\space)", &env);
+    assert_eq!(mixed.to_string(), "(\"a\" #\\b #\\space)");
+    assert_eq!(Displayed(&mixed).to_string(), "(a b  )");
+}
+
+#[test]
+fn char_procedures_convert_and_classify() {
+    let env = global_env();
+    assert_eq!(run("(char->integer #\\tab)", &env), atom_num!(9));
+    assert_eq!(run("(char-upcase #\\ä)", &env).to_string(), "#\\Ä");
+    assert_eq!(run("(char-downcase #\\A)", &env).to_string(), "#\\a");
+    assert_eq!(
+        run("(list (char? #\\x) (char? \"x\"))", &env).to_string(),
+        "(#t #f)"
+    );
+    assert_eq!(run("(char-alphabetic? #\\3)", &env), atom_bool!(false));
+    assert_eq!(run("(digit-value #\\7)", &env), atom_num!(7));
+    assert!(matches!(
+        eval_source("(integer->char #\\a)", &env),
+        Err(Error::Eval(EvalError::TypeMismatch(..)))
+    ));
+    assert!(matches!(
+        eval_source("(char-upcase 1)", &env),
+        Err(Error::Eval(EvalError::TypeMismatch(..)))
+    ));
+}
+
+#[test]
+fn char_comparisons_chain_and_ignore_case_with_ci() {
+    let env = global_env();
+    assert_eq!(run("(char<? #\\a #\\b #\\c)", &env), atom_bool!(true));
+    assert_eq!(run("(char<? #\\a #\\B)", &env), atom_bool!(false));
+    assert_eq!(run("(char-ci<? #\\a #\\B)", &env), atom_bool!(true));
+    assert_eq!(run("(char-ci=? #\\Z #\\z)", &env), atom_bool!(true));
+}
+
+#[test]
+fn string_literals_read_escapes() {
+    let env = global_env();
+    assert_eq!(
+        run("\"say \\\"hi\\\"\"", &env).to_string(),
+        "\"say \\\"hi\\\"\""
+    );
+    assert_eq!(
+        run("\"a\\tb\\x41;\\\\\"", &env).to_string(),
+        "\"a\\tbA\\\\\""
+    );
+    assert_eq!(run("\"one \\\n     two\"", &env).to_string(), "\"one two\"");
+    assert!(matches!(
+        eval_source("\"bad \\q escape\"", &env),
+        Err(Error::Parse(ParserErr::BadEscape(_)))
+    ));
+    assert!(matches!(
+        eval_source("\"unterminated \\\"", &env),
+        Err(Error::Tokenize(TokenizeErr::UnterminatedString(_)))
+    ));
+}
+
+#[test]
+fn strings_are_indexed_by_char() {
+    let env = global_env();
+    assert_eq!(run("(string-length \"añb\")", &env), atom_num!(3));
+    assert_eq!(run("(string-ref \"añb\" 1)", &env).to_string(), "#\\ñ");
+    assert_eq!(run("(substring \"héllo\" 1 3)", &env).to_string(), "\"él\"");
+    assert_eq!(run("(string-index \"hello\" #\\l)", &env), atom_num!(2));
+    assert_eq!(
+        run("(string-index \"ab1\" char-numeric?)", &env),
+        atom_num!(2)
+    );
+    assert_eq!(
+        run("(string-contains \"ñandú\" \"dú\")", &env),
+        atom_num!(3)
+    );
+    assert!(matches!(
+        eval_source("(string-ref \"abc\" 3)", &env),
+        Err(Error::Eval(EvalError::IndexOutOfRange(3, 3)))
+    ));
+}
+
+#[test]
+fn strings_convert_to_and_from_other_types() {
+    let env = global_env();
+    assert_eq!(
+        run("(string->list \"ab\")", &env).to_string(),
+        "(#\\a #\\b)"
+    );
+    assert_eq!(run("(string->symbol \"xy\")", &env).to_string(), "xy");
+    assert_eq!(run("(number->string 255 16)", &env).to_string(), "\"ff\"");
+    assert_eq!(run("(string->number \"ff\" 16)", &env), atom_num!(255));
+    assert_eq!(run("(string->number \"#b101\")", &env), atom_num!(5));
+    assert_eq!(run("(string->number \"abc\")", &env), atom_bool!(false));
+}
+
+#[test]
+fn string_library_builds_and_splits_strings() {
+    let env = global_env();
+    assert_eq!(
+        run("(string-append \"a\" \"\" \"bc\")", &env).to_string(),
+        "\"abc\""
+    );
+    assert_eq!(
+        run("(string-upcase \"straße\")", &env).to_string(),
+        "\"STRASSE\""
+    );
+    assert_eq!(
+        run("(string-split \"a,b,,c\" #\\,)", &env).to_string(),
+        "(\"a\" \"b\" \"\" \"c\")"
+    );
+    assert_eq!(
+        run("(string-split \"  a  b \")", &env).to_string(),
+        "(\"a\" \"b\")"
+    );
+    assert_eq!(
+        run("(string-join '(\"a\" \"b\" \"c\") \", \")", &env).to_string(),
+        "\"a, b, c\""
+    );
+    assert_eq!(run("(string<? \"abc\" \"abd\")", &env), atom_bool!(true));
+    assert!(matches!(
+        eval_source("(string-append \"a\" 1)", &env),
+        Err(Error::Eval(EvalError::TypeMismatch(..)))
+    ));
+}
+
+#[test]
+fn strings_are_changed_in_place() {
+    let env = global_env();
+    assert_eq!(
+        run_all(
+            "(define s (make-string 3 #\\a)) (string-set! s 1 #\\ñ) s",
+            &env
+        )
+        .to_string(),
+        "\"aña\""
+    );
+    assert_eq!(
+        run_all("(define t s) (string-fill! t #\\z 2) s", &env).to_string(),
+        "\"añz\""
+    );
+    assert_eq!(
+        run(
+            "(list (eqv? s t) (eqv? s (string-copy s)) (equal? s (string-copy s)))",
+            &env
+        )
+        .to_string(),
+        "(#t #f #t)"
+    );
+    assert_eq!(
+        run_all(
+            "(define u (string-copy \"hello\")) (string-copy! u 1 u 0 3) u",
+            &env
+        )
+        .to_string(),
+        "\"hhelo\""
+    );
+    assert!(matches!(
+        eval_source("(string-set! s 3 #\\x)", &env),
+        Err(Error::Eval(EvalError::IndexOutOfRange(3, 3)))
+    ));
+}
+
+#[test]
+fn output_string_ports_collect_what_is_written() {
+    let env = global_env();
+    let src = "(define out (open-output-string))
+               (for-each (lambda (x) (write x out) (write-char #\\space out)) '(1 \"a\" #\\b))
+               (get-output-string out)";
+    assert_eq!(run_all(src, &env).to_string(), "\"1 \\\"a\\\" #\\\\b \"");
+    let src = "(define buf (open-output-string))
+               (parameterize ((current-output-port buf)) (display \"hi\") (newline))
+               (get-output-string buf)";
+    assert_eq!(run_all(src, &env).to_string(), "\"hi\\n\"");
+}
+
+#[test]
+fn input_string_ports_are_read_until_eof() {
+    let env = global_env();
+    let src = "(define in (open-input-string \"ab\\ncd\"))
+               (list (peek-char in) (read-char in) (read-line in) (read-string 5 in))";
+    assert_eq!(run_all(src, &env).to_string(), "(#\\a #\\a \"b\" \"cd\")");
+    assert_eq!(run("(eof-object? (read-char in))", &env), atom_bool!(true));
+    assert!(matches!(
+        eval_source("(display 1 in)", &env),
+        Err(Error::Eval(EvalError::ExprTypeMismatch(..)))
+    ));
+}
+
+#[test]
+fn string_literals_are_fresh_on_every_evaluation() {
+    let env = global_env();
+    run("(define (f) \"abc\")", &env);
+    run("(string-set! (f) 0 #\\z)", &env);
+    assert_eq!(run("(f)", &env).to_string(), "\"abc\"");
+}
+
+#[test]
+fn delay_force_shares_the_promise_it_forces() {
+    let env = global_env();
+    run_all(
+        "(define out (open-output-string))
+         (define p (delay (begin (display \"once \" out) 1)))
+         (define q (delay-force p))",
+        &env,
+    );
+    assert_eq!(run_all("(list (force q) (force p))", &env).to_string(), "(1 1)");
+    assert_eq!(run("(get-output-string out)", &env).to_string(), "\"once \"");
+}
+
+#[test]
+fn parameterize_body_defines_stay_local() {
+    let env = global_env();
+    run("(define p (make-parameter 1))", &env);
+    assert_eq!(run("(parameterize ((p 2)) (define zz 5) (+ zz (p)))", &env), atom_num!(7));
+    assert!(matches!(
+        eval_source("zz", &env),
+        Err(Error::Eval(EvalError::UndefinedSymbol(_)))
+    ));
+}
+
+#[test]
+fn eq_compares_lists_by_identity() {
+    let env = global_env();
+    run("(define l '(1 2))", &env);
+    assert_eq!(run("(eq? l l)", &env), atom_bool!(true));
+    assert_eq!(run("(eq? '(1) '(1))", &env), atom_bool!(false));
+    assert_eq!(run("(eqv? (list 1) (list 1))", &env), atom_bool!(false));
+    assert_eq!(run("(equal? (list 1) (list 1))", &env), atom_bool!(true));
+}
+
+#[test]
+fn cdr_and_cons_copy_the_pairs_they_would_share() {
+    let env = global_env();
+    run("(define l (list 1 (list 2) 3))", &env);
+    run("(define t (cdr l))", &env);
+    assert_eq!(run("(eq? t t)", &env), atom_bool!(true));
+    assert_eq!(run("(eq? (car (cdr l)) (car t))", &env), atom_bool!(true));
+    assert_eq!(run("(eq? t (cdr l))", &env), atom_bool!(false));
+    assert_eq!(run("(eq? (cdr l) (cdr l))", &env), atom_bool!(false));
+    assert_eq!(run("(eq? (cdr (cons 0 l)) l)", &env), atom_bool!(false));
+    assert_eq!(run("(equal? (cdr l) (cdr l))", &env), atom_bool!(true));
+}
+
+#[test]
+fn eqv_tells_apart_numbers_that_equal_does_not() {
+    let env = global_env();
+    assert_eq!(run("(eqv? 0.0 -0.0)", &env), atom_bool!(false));
+    assert_eq!(run("(eqv? 1.5 1.5)", &env), atom_bool!(true));
+    assert_eq!(run("(eqv? 1 1.0)", &env), atom_bool!(false));
+}
+
+#[test]
+fn eqv_compares_strings_and_lambdas_by_identity() {
+    let env = global_env();
+    assert_eq!(run("(eqv? \"a\" \"a\")", &env), atom_bool!(false));
+    assert_eq!(run("(equal? \"a\" \"a\")", &env), atom_bool!(true));
+    assert_eq!(
+        run("(eqv? (lambda (x) x) (lambda (x) x))", &env),
+        atom_bool!(false)
+    );
+    assert_eq!(
+        run("(let ((f (lambda (x) x))) (eqv? f f))", &env),
+        atom_bool!(true)
+    );
+}
+
+#[test]
+fn gensyms_never_clash_with_symbols_read() {
+    let env = global_env();
+    run_all(
+        "(define-macro (add-one v)
+           (let ((g (gensym)))
+             `(let ((,g 1)) (+ ,g ,v))))
+         (define g.1 10)
+         (define g.2 10)",
+        &env,
+    );
+    assert_eq!(run("(add-one (+ g.1 g.2))", &env), atom_num!(21));
+    assert_eq!(run("(eq? (gensym) 'g.3)", &env), atom_bool!(false));
+    assert!(run("(symbol->string (gensym 'tmp))", &env)
+        .to_string()
+        .starts_with("\"tmp."));
+}
+
+#[test]
+fn template_binders_are_renamed_only_where_they_bind() {
+    let env = global_env();
+    run_all(
+        "(define-syntax m
+           (syntax-rules ()
+             ((_ x) (begin (list x) (let ((list 1)) list)))))
+         (define-syntax swap!
+           (syntax-rules ()
+             ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))",
+        &env,
+    );
+    assert_eq!(run("(m 5)", &env), atom_num!(1));
+    assert_eq!(
+        run_all("(define tmp 1) (define y 2) (swap! tmp y) (list tmp y)", &env).to_string(),
+        "(2 1)"
+    );
+}
+
+#[test]
+fn free_template_identifiers_refer_to_where_the_macro_was_defined() {
+    let env = global_env();
+    run("(define-syntax my-or2 (syntax-rules () ((_ a b) (let ((t a)) (if t t b)))))", &env);
+    assert_eq!(run_forms("(let ((if list)) (my-or2 #f 2))", &env), atom_num!(2));
+    assert_eq!(run_forms("((lambda (if) (my-or2 #f 3)) 0)", &env), atom_num!(3));
+
+    // Variables are read and assigned there as well
+    run_forms(
+        "(define n 1)
+         (define-syntax get-n (syntax-rules () ((_) n)))
+         (define-syntax inc! (syntax-rules () ((_) (set! n (+ n 1)))))
+         (define (f n) (inc!) (get-n))",
+        &env,
+    );
+    assert_eq!(run_forms("(f 10)", &env), atom_num!(2));
+    assert_eq!(run_forms("(set! n 7) (f 10)", &env), atom_num!(8));
+    assert_eq!(run_forms("(let ((n 0)) (inc!) n)", &env), atom_num!(0));
+    assert_eq!(run("n", &env), atom_num!(9));
+
+    // Also for local macros, which the evaluator expands
+    let src = "(let ((x 1)) (let-syntax ((m (syntax-rules () ((_) x)))) (let ((x 2)) (m))))";
+    assert_eq!(run(src, &env), atom_num!(1));
+}
+
+#[test]
+fn macros_in_lambda_bodies_expand_once() {
+    let env = global_env();
+    run_forms(
+        "(define expansions 0)
+         (define-macro (counted x)
+           (set! expansions (+ expansions 1))
+           x)
+         (define (f) (counted 7))",
+        &env,
+    );
+    assert_eq!(run_forms("(f) (f) (f)", &env), atom_num!(7));
+    assert_eq!(run("expansions", &env), atom_num!(1));
+
+    // Nor when the lambda holding them is made on every call
+    run_forms("(define (g) (map (lambda (x) (counted x)) '(1 2)))", &env);
+    assert_eq!(run_forms("(g) (g)", &env).to_string(), "(1 2)");
+    assert_eq!(run("expansions", &env), atom_num!(2));
+    run_forms("(eval '(define (h) (counted 3)))", &env);
+    assert_eq!(run_forms("(h) (h)", &env), atom_num!(3));
+    assert_eq!(run("expansions", &env), atom_num!(3));
+
+    // Variables shadow macros of the same name
+    assert_eq!(
+        run_forms("((lambda (counted) (counted 2)) -)", &env),
+        atom_num!(-2)
+    );
+    assert_eq!(
+        run_forms("(let () (define (counted x) (* x 3)) (counted 2))", &env),
+        atom_num!(6)
+    );
+    assert_eq!(run("expansions", &env), atom_num!(3));
+}
@@ -0,0 +1,35 @@
+use crate::tokenizer::Span;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum TokenizeErr {
+    UnterminatedString(Span),
+}
+
+impl TokenizeErr {
+    pub fn span(&self) -> Span {
+        match &self {
+            Self::UnterminatedString(span) => *span,
+        }
+    }
+
+    // Error message followed by the offending source
+    pub fn report(&self, source: &str) -> String {
+        format!("{}\n{}", self, self.span().underline(source))
+    }
+}
+
+impl fmt::Display for TokenizeErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Self::UnterminatedString(span) => write!(
+                f,
+                "{}: Unexpected EOF reading string, '\"' may be missing (unterminated string)",
+                span.start
+            ),
+        }
+    }
+}
+
+impl Error for TokenizeErr {}
@@ -1,18 +1,110 @@
+use std::fmt;
 use std::string::String;
 
-#[derive(Debug)]
+pub mod error;
+use error::TokenizeErr;
+
+#[derive(Debug, Clone, Copy)]
 pub struct TokenRange {
-    start: usize,
-    end: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Both line and column start at 1, columns are counted in chars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
 }
 
 #[derive(Debug)]
 pub struct Token {
     pub value: String,
     pub range: TokenRange,
+    pub span: Span,
     pub ttype: TokenType,
 }
 
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl Span {
+    pub fn contains(&self, other: &Span) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    /// Renders the first line of the span with a caret under it:
+    ///
+    /// ```text
+    ///   |
+    /// 2 | (car 5)
+    ///   | ^^^^^^^
+    /// ```
+    pub fn underline(&self, source: &str) -> String {
+        let text = source.lines().nth(self.start.line - 1).unwrap_or("");
+        let start = self.start.column - 1;
+        let end = if self.end.line == self.start.line {
+            self.end.column - 1
+        } else {
+            text.chars().count()
+        };
+
+        // Keep tabs so the carets line up with the source
+        let mut carets: String = text
+            .chars()
+            .take(start)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        carets.push_str(&"^".repeat(end.saturating_sub(start).max(1)));
+
+        let number = self.start.line.to_string();
+        let gutter = " ".repeat(number.len());
+        format!(
+            "{} |\n{} | {}\n{} | {}",
+            gutter, number, text, gutter, carets
+        )
+    }
+}
+
+// Turns increasing byte offsets into line/column positions
+struct Cursor<'a> {
+    source: &'a str,
+    offset: usize,
+    pos: Position,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(source: &'a str) -> Cursor<'a> {
+        Cursor {
+            source,
+            offset: 0,
+            pos: Position { line: 1, column: 1 },
+        }
+    }
+
+    fn advance_to(&mut self, offset: usize) -> Position {
+        for c in self.source[self.offset..offset].chars() {
+            if c == '\n' {
+                self.pos.line += 1;
+                self.pos.column = 1;
+            } else {
+                self.pos.column += 1;
+            }
+        }
+        self.offset = offset;
+        self.pos
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum TokenType {
     Opc,
@@ -20,35 +112,47 @@ pub enum TokenType {
     Quo,
     Quasi,
     Unquo,
+    UnquoSplice,
     Dot,
     Str,
+    Char,
     Other,
     Eof,
 }
 
+// All the offsets below are byte offsets into the given str
+
 fn skip_whitespace(s: &str) -> usize {
-    match s.chars().position(|c| !c.is_whitespace()) {
+    match s.char_indices().find(|(_, c)| !c.is_whitespace()) {
         None => s.len(),
-        Some(pos) => pos,
+        Some((pos, _)) => pos,
     }
 }
 
 fn skip_to_char(s: &str, c: char) -> Option<usize> {
-    // println!("{}", s);
-    s.chars().position(|x| x == c)
+    s.find(c)
 }
 
-fn next_valid_symbol(s: &str) -> usize {
-    for (i, c) in s.chars().enumerate() {
-        if c.is_whitespace() {
-            return i;
-        }
+// Offset of the `"' that closes a string, skipping the escaped ones
+fn string_end(s: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
         match c {
-            '(' | ')' | '"' | '\'' | ';' | ',' | '.' => return i,
-            _ => continue,
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(i),
+            _ => {}
         }
     }
-    s.len()
+    None
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '"' | '\'' | '`' | ';' | ',')
+}
+
+fn next_valid_symbol(s: &str) -> usize {
+    s.find(is_delimiter).unwrap_or(s.len())
 }
 
 fn next_token(s: &str) -> Option<TokenRange> {
@@ -72,7 +176,12 @@ fn next_token(s: &str) -> Option<TokenRange> {
     }
 
     match sc[pos] as char {
-        '(' | ')' | '.' | '\'' | ',' => {
+        ',' if s[pos + 1..].starts_with('@') => Some(TokenRange {
+            start: pos,
+            end: pos + 2,
+        }),
+
+        '(' | ')' | '\'' | '`' | ',' => {
             Some(TokenRange {
                 // ttype: TokenType::Symbol,
                 start: pos,
@@ -80,10 +189,27 @@ fn next_token(s: &str) -> Option<TokenRange> {
             })
         }
 
+        // A dot on its own separates the cdr of a list,
+        // otherwise it is part of an atom such as `1.5' or `...'
+        '.' if s[pos + 1..].chars().next().is_none_or(is_delimiter) => Some(TokenRange {
+            start: pos,
+            end: pos + 1,
+        }),
+
+        // The char after `#\' belongs to the literal even when it is a
+        // delimiter, as in `#\(' or `#\ '
+        '#' if s[pos + 1..].starts_with('\\') && s.len() > pos + 2 => {
+            let start = pos + 2;
+            let first = s[start..].chars().next().map_or(1, char::len_utf8);
+            Some(TokenRange {
+                start: pos,
+                end: start + first + next_valid_symbol(&s[start + first..]),
+            })
+        }
+
         '"' => {
             let sl = &s[pos + 1..];
-            let end = skip_to_char(sl, '"');
-            // println!("{} end: {:?}", sl.len(), end);
+            let end = string_end(sl);
             Some(TokenRange {
                 // ttype: TokenType::Str,
                 start: pos,
@@ -97,18 +223,20 @@ fn next_token(s: &str) -> Option<TokenRange> {
         }
 
         _ => {
+            let first = s[pos..].chars().next().map_or(1, char::len_utf8);
             Some(TokenRange {
                 // ttype: TokenType::Name,
                 start: pos,
-                end: pos + next_valid_symbol(&s[pos + 1..]) + 1,
+                end: pos + first + next_valid_symbol(&s[pos + first..]),
             })
         }
     }
 }
 
-pub fn tokenize(s: &'_ str) -> Vec<Token> {
+pub fn tokenize(s: &'_ str) -> Result<Vec<Token>, TokenizeErr> {
     let mut res = Vec::new();
     let mut offs = 0;
+    let mut cursor = Cursor::new(s);
 
     while let Some(tr) = next_token(&s[offs..]) {
         // loop {
@@ -138,14 +266,24 @@ pub fn tokenize(s: &'_ str) -> Vec<Token> {
             ')' => TokenType::Clc,
             '\'' => TokenType::Quo,
             '`' => TokenType::Quasi,
+            ',' if val == ",@" => TokenType::UnquoSplice,
             ',' => TokenType::Unquo,
-            '.' => TokenType::Dot,
+            '.' if val.len() == 1 => TokenType::Dot,
             '"' => TokenType::Str,
+            '#' if val.starts_with("#\\") => TokenType::Char,
             _ => TokenType::Other,
         };
+        let span = Span {
+            start: cursor.advance_to(tok_ran.start),
+            end: cursor.advance_to(tok_ran.end),
+        };
+        if ttype == TokenType::Str && string_end(&val[1..]).map(|end| end + 2) != Some(val.len()) {
+            return Err(TokenizeErr::UnterminatedString(span));
+        }
         let t = Token {
             value: String::from(val),
             range: tok_ran,
+            span,
             ttype,
         };
         res.push(t);
@@ -158,5 +296,5 @@ pub fn tokenize(s: &'_ str) -> Vec<Token> {
     //     ttype: TokenType::EOF,
     // });
     res.reverse();
-    res
+    Ok(res)
 }
@@ -1,7 +1,11 @@
+use crate::evaluator::error::EvalError;
 use crate::evaluator::eval;
 use crate::native::*;
-use crate::parser::{Atom, Expr, NativeEnc};
+use crate::number::Number;
+use crate::parser::{Atom, Expr, List, NativeEnc, SyntaxEnc};
+use crate::port;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[macro_export]
 macro_rules! atom_nil {
@@ -13,7 +17,23 @@ macro_rules! atom_nil {
 #[macro_export]
 macro_rules! atom_num {
     ($a:expr) => {
-        Expr::Atom(Box::new(Atom::Num($a)))
+        Expr::Atom(Box::new(Atom::Num($crate::number::Number::from($a))))
+    };
+}
+
+#[macro_export]
+macro_rules! atom_bool {
+    ($b:expr) => {
+        Expr::Atom(Box::new(Atom::Bool($b)))
+    };
+}
+
+#[macro_export]
+macro_rules! atom_str {
+    ($s:expr $(,)?) => {
+        $crate::parser::Expr::Atom(Box::new($crate::parser::Atom::Str(std::rc::Rc::new(
+            std::cell::RefCell::new($s),
+        ))))
     };
 }
 
@@ -24,6 +44,13 @@ macro_rules! atom_native {
     };
 }
 
+#[macro_export]
+macro_rules! atom_syntax {
+    ($name:ident) => {
+        Atom::Syntax(SyntaxEnc($name))
+    };
+}
+
 #[macro_export]
 macro_rules! expr_atom {
     ($atom:expr) => {
@@ -37,6 +64,12 @@ macro_rules! insert_native {
     };
 }
 
+macro_rules! insert_syntax {
+    ($id:ident, $symbol:expr, $func:ident) => {
+        $id.insert($symbol.to_string(), expr_atom!(atom_syntax!($func)));
+    };
+}
+
 pub fn expr_is_nil(expr: &Expr) -> bool {
     if let Expr::Atom(a) = expr {
         matches!(**a, Atom::Nil)
@@ -45,20 +78,268 @@ pub fn expr_is_nil(expr: &Expr) -> bool {
     }
 }
 
+// Builds a proper list out of its elements
+pub fn list_from(mut items: List) -> Expr {
+    if items.is_empty() {
+        atom_nil!()
+    } else {
+        items.push_back(atom_nil!());
+        Expr::List(Rc::new(items))
+    }
+}
+
+// The elements of a list, copied only if the list is shared
+pub fn unshare(list: Rc<List>) -> List {
+    Rc::try_unwrap(list).unwrap_or_else(|l| (*l).clone())
+}
+
+// The elements of a proper list, without the nil that ends it
+pub fn list_to_args(expr: Expr) -> Result<List, EvalError> {
+    match expr {
+        Expr::List(l) => match unshare(l) {
+            mut l if l.back().is_some_and(expr_is_nil) => {
+                l.pop_back();
+                Ok(l)
+            }
+            _ => Err(EvalError::DottedList),
+        },
+        nil if expr_is_nil(&nil) => Ok(List::new()),
+        e => Err(EvalError::ExprTypeMismatch("list".to_string(), e)),
+    }
+}
+
+// Makes the result of `values' out of `vals'
+pub fn values_from(mut vals: List) -> Expr {
+    match vals.len() {
+        1 => vals.pop_front().unwrap(),
+        _ => Expr::Values(Box::new(vals.into_iter().collect())),
+    }
+}
+
+// The values held by `expr', a single one unless it came from `values'
+pub fn values_to_args(expr: Expr) -> List {
+    match expr {
+        Expr::Values(vals) => vals.into_iter().collect(),
+        expr => List::from([expr]),
+    }
+}
+
+pub fn is_procedure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Atom(a) => matches!(**a, Atom::Native(_)),
+        Expr::Lambda(_) | Expr::CaseLambda(_) | Expr::Continuation(_) | Expr::Parameter(_) => true,
+        _ => false,
+    }
+}
+
+// Whether two values are the same object. Symbols, booleans, chars and
+// numbers are the same when they look the same, as long as numbers agree
+// in exactness and floats down to their sign. Anything that can be shared,
+// pairs, strings or procedures, is only the same as itself. A list is held
+// whole rather than as linked pairs, so cdr and cons copy the pairs they
+// would share in Scheme: only the very list taken, as out of a variable or
+// with car, is eq? to itself.
+pub fn is_eqv(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Atom(x), Expr::Atom(y)) => match (&**x, &**y) {
+            (Atom::Str(x), Atom::Str(y)) => Rc::ptr_eq(x, y),
+            (Atom::Num(Number::Real(x)), Atom::Num(Number::Real(y))) => x.to_bits() == y.to_bits(),
+            _ => x == y,
+        },
+        (Expr::List(x), Expr::List(y)) => Rc::ptr_eq(x, y),
+        (Expr::Lambda(x), Expr::Lambda(y)) => Rc::ptr_eq(x, y),
+        (Expr::CaseLambda(x), Expr::CaseLambda(y)) => Rc::ptr_eq(x, y),
+        (Expr::Macro(x), Expr::Macro(y)) => Rc::ptr_eq(x, y),
+        (Expr::Continuation(x), Expr::Continuation(y)) => Rc::ptr_eq(x, y),
+        (Expr::Condition(x), Expr::Condition(y)) => Rc::ptr_eq(x, y),
+        (Expr::Promise(x), Expr::Promise(y)) => Rc::ptr_eq(x, y),
+        (Expr::Parameter(x), Expr::Parameter(y)) => Rc::ptr_eq(x, y),
+        (Expr::Port(x), Expr::Port(y)) => Rc::ptr_eq(x, y),
+        _ => false,
+    }
+}
+
+// Whether two values print the same, the one structural comparison.
+// Lists and strings are compared by their contents, the rest as eqv?.
+pub fn is_equal(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::List(x), Expr::List(y)) => {
+            x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| is_equal(a, b))
+        }
+        (Expr::Atom(x), Expr::Atom(y)) => match (&**x, &**y) {
+            (Atom::Str(x), Atom::Str(y)) => *x.borrow() == *y.borrow(),
+            _ => is_eqv(a, b),
+        },
+        (Expr::Quote(x), Expr::Quote(y))
+        | (Expr::Quasiquote(x), Expr::Quasiquote(y))
+        | (Expr::Unquote(x), Expr::Unquote(y))
+        | (Expr::UnquoteSplicing(x), Expr::UnquoteSplicing(y)) => is_equal(x, y),
+        (Expr::Values(x), Expr::Values(y)) => {
+            x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| is_equal(a, b))
+        }
+        (a, b) => is_eqv(a, b),
+    }
+}
+
 pub fn init_map(map: &mut HashMap<String, Expr>) {
+    // Special forms, they get their operands unevaluated
+    insert_syntax!(map, "quote", quote);
+    insert_syntax!(map, "quasiquote", quasiquote);
+    insert_syntax!(map, "lambda", lambda);
+    insert_syntax!(map, "case-lambda", case_lambda);
+    insert_syntax!(map, "define", define);
+    insert_syntax!(map, "set!", set);
+    insert_syntax!(map, "if", ifcond);
+    insert_syntax!(map, "let", let_form);
+    insert_syntax!(map, "let*", let_star);
+    insert_syntax!(map, "letrec", letrec);
+    insert_syntax!(map, "letrec*", letrec_star);
+    insert_syntax!(map, "begin", begin);
+    insert_syntax!(map, "and", and);
+    insert_syntax!(map, "or", or);
+    insert_syntax!(map, "when", when);
+    insert_syntax!(map, "unless", unless);
+    insert_syntax!(map, "cond", cond);
+    insert_syntax!(map, "case", case);
+    insert_syntax!(map, "syntax-rules", syntax_rules);
+    insert_syntax!(map, "define-syntax", define_syntax);
+    insert_syntax!(map, "let-syntax", let_syntax);
+    insert_syntax!(map, "letrec-syntax", letrec_syntax);
+    insert_syntax!(map, "define-macro", define_macro);
+    insert_syntax!(map, "guard", guard);
+    insert_syntax!(map, "let-values", let_values);
+    insert_syntax!(map, "let*-values", let_star_values);
+    insert_syntax!(map, "define-values", define_values);
+    insert_syntax!(map, "receive", receive);
+    insert_syntax!(map, "delay", delay);
+    insert_syntax!(map, "delay-force", delay_force);
+    insert_syntax!(map, "stream-cons", stream_cons);
+    insert_syntax!(map, "parameterize", parameterize);
+
+    // Procedures
     insert_native!(map, "+", add);
     insert_native!(map, "-", sub);
     insert_native!(map, "*", mul);
     insert_native!(map, "/", div);
-    insert_native!(map, "set!", set);
-    insert_native!(map, "define", define);
+    insert_native!(map, "=", num_eq);
+    insert_native!(map, "<", num_lt);
+    insert_native!(map, ">", num_gt);
+    insert_native!(map, "<=", num_le);
+    insert_native!(map, ">=", num_ge);
+    // Everything eqv? tells apart eq? can too
+    insert_native!(map, "eq?", eqv);
+    insert_native!(map, "eqv?", eqv);
+    insert_native!(map, "equal?", equal);
+    insert_native!(map, "not", not);
     insert_native!(map, "inspect", inspect);
     insert_native!(map, "eval", eval);
-    insert_native!(map, "quote", quote);
     insert_native!(map, "cons", cons);
-    insert_native!(map, "lambda", lambda);
-    insert_native!(map, "if", ifcond);
+    insert_native!(map, "list", list);
     insert_native!(map, "car", car);
     insert_native!(map, "cdr", cdr);
+    insert_native!(map, "procedure?", procedure_p);
+    insert_native!(map, "apply", apply);
+    insert_native!(map, "map", mapcar);
+    insert_native!(map, "for-each", for_each);
+    insert_native!(map, "call-with-current-continuation", call_cc);
+    insert_native!(map, "call/cc", call_cc);
+    insert_native!(map, "dynamic-wind", dynamic_wind);
+    insert_native!(map, "error", error);
+    insert_native!(map, "raise", raise);
+    insert_native!(map, "raise-continuable", raise_continuable);
+    insert_native!(map, "with-exception-handler", with_exception_handler);
+    insert_native!(map, "error-object?", error_object_p);
+    insert_native!(map, "error-object-message", error_object_message);
+    insert_native!(map, "error-object-irritants", error_object_irritants);
+    insert_native!(map, "values", values);
+    insert_native!(map, "call-with-values", call_with_values);
+    insert_native!(map, "make-promise", make_promise);
+    insert_native!(map, "force", force);
+    insert_native!(map, "promise?", promise_p);
+    insert_native!(map, "stream-null?", stream_null_p);
+    insert_native!(map, "stream-pair?", stream_pair_p);
+    insert_native!(map, "stream-car", stream_car);
+    insert_native!(map, "stream-cdr", stream_cdr);
+    insert_native!(map, "stream-map", stream_map);
+    insert_native!(map, "stream-filter", stream_filter);
+    insert_native!(map, "stream-take", stream_take);
+    insert_native!(map, "list->stream", list_to_stream);
+    insert_native!(map, "stream->list", stream_to_list);
+    insert_native!(map, "make-parameter", make_parameter);
+    insert_native!(map, "display", display);
+    insert_native!(map, "write", write);
+    insert_native!(map, "newline", newline);
+    insert_native!(map, "write-string", write_string);
+    insert_native!(map, "write-char", write_char);
+    insert_native!(map, "open-output-string", open_output_string);
+    insert_native!(map, "get-output-string", get_output_string);
+    insert_native!(map, "open-input-string", open_input_string);
+    insert_native!(map, "read-char", read_char);
+    insert_native!(map, "peek-char", peek_char);
+    insert_native!(map, "read-line", read_line);
+    insert_native!(map, "read-string", read_string);
+    insert_native!(map, "eof-object", eof_object);
+    insert_native!(map, "eof-object?", eof_object_p);
+    insert_native!(map, "port?", port_p);
+    insert_native!(map, "input-port?", input_port_p);
+    insert_native!(map, "output-port?", output_port_p);
+    insert_native!(map, "char?", char_p);
+    insert_native!(map, "char->integer", char_to_integer);
+    insert_native!(map, "integer->char", integer_to_char);
+    insert_native!(map, "char-upcase", char_upcase);
+    insert_native!(map, "char-downcase", char_downcase);
+    insert_native!(map, "char-foldcase", char_foldcase);
+    insert_native!(map, "char-alphabetic?", char_alphabetic_p);
+    insert_native!(map, "char-numeric?", char_numeric_p);
+    insert_native!(map, "char-whitespace?", char_whitespace_p);
+    insert_native!(map, "char-upper-case?", char_upper_case_p);
+    insert_native!(map, "char-lower-case?", char_lower_case_p);
+    insert_native!(map, "digit-value", digit_value);
+    insert_native!(map, "char=?", char_eq);
+    insert_native!(map, "char<?", char_lt);
+    insert_native!(map, "char>?", char_gt);
+    insert_native!(map, "char<=?", char_le);
+    insert_native!(map, "char>=?", char_ge);
+    insert_native!(map, "char-ci=?", char_ci_eq);
+    insert_native!(map, "char-ci<?", char_ci_lt);
+    insert_native!(map, "char-ci>?", char_ci_gt);
+    insert_native!(map, "char-ci<=?", char_ci_le);
+    insert_native!(map, "char-ci>=?", char_ci_ge);
+    insert_native!(map, "string?", string_p);
+    insert_native!(map, "string", string);
+    insert_native!(map, "make-string", make_string);
+    insert_native!(map, "string-copy", string_copy);
+    insert_native!(map, "string-set!", string_set);
+    insert_native!(map, "string-fill!", string_fill);
+    insert_native!(map, "string-copy!", string_copy_to);
+    insert_native!(map, "string-length", string_length);
+    insert_native!(map, "string-ref", string_ref);
+    insert_native!(map, "substring", substring);
+    insert_native!(map, "string-append", string_append);
+    insert_native!(map, "string->list", string_to_list);
+    insert_native!(map, "list->string", list_to_string);
+    insert_native!(map, "string->symbol", string_to_symbol);
+    insert_native!(map, "symbol->string", symbol_to_string);
+    insert_native!(map, "number->string", number_to_string);
+    insert_native!(map, "string->number", string_to_number);
+    insert_native!(map, "string-upcase", string_upcase);
+    insert_native!(map, "string-downcase", string_downcase);
+    insert_native!(map, "string-index", string_index);
+    insert_native!(map, "string-contains", string_contains);
+    insert_native!(map, "string-split", string_split);
+    insert_native!(map, "string-join", string_join);
+    insert_native!(map, "string=?", string_eq);
+    insert_native!(map, "string<?", string_lt);
+    insert_native!(map, "string>?", string_gt);
+    insert_native!(map, "string<=?", string_le);
+    insert_native!(map, "string>=?", string_ge);
+    insert_native!(map, "gensym", gensym);
+    insert_native!(map, "macroexpand", macroexpand);
+    insert_native!(map, "macroexpand-1", macroexpand_1);
     map.insert("nil".to_string(), atom_nil!());
+    map.insert("stream-null".to_string(), stream_null());
+    map.insert(
+        "current-output-port".to_string(),
+        Expr::Parameter(port::current_output()),
+    );
 }